@@ -1,27 +1,71 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use crate::{
+    blockchain::target::{self, GENESIS_BITS},
+    transaction::transaction::Transaction,
+    utils::{
+        hash::{deserialize_digest, digest, merkle_proof, merkle_root, Hash, MerkleProof},
+        time,
+    },
+};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::utils::{hash::Hash, time};
-use serde::{Deserialize, Serialize};
+/// Layout version written at the start of every encoded header.
+pub const HEADER_VERSION: u32 = 2;
+/// Size of an encoded header in bytes.
+pub const HEADER_SIZE: usize = 4 + 32 * 3 + 8 * 3 + 4;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Block {
     pub timestamp: u64,
     pub nonce: usize,
+    #[serde(deserialize_with = "deserialize_digest")]
     pub prev: Hash,
     pub height: usize,
-    pub data: String,
-    pub difficulty: usize,
+    /// Root of the Merkle tree over the hashes of `transactions`.
+    #[serde(deserialize_with = "deserialize_digest")]
+    pub merkle_root: Hash,
+    pub transactions: Vec<Transaction>,
+    /// Free-form data the miner attached to the block.
+    pub memo: Option<String>,
+    /// Compact target the block hash must not exceed, see `target::from_compact`.
+    pub bits: u32,
+}
+
+/// The part of a block its hash commits to, with the body reduced to digests.
+///
+/// Headers are hashed and sent over the wire in a fixed binary layout, see
+/// `Header::encode`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub version: u32,
+    pub timestamp: u64,
+    pub nonce: usize,
+    pub prev: Hash,
+    pub height: usize,
+    pub merkle_root: Hash,
+    /// Hash of the memo, blank when there is none.
+    pub memo: Hash,
+    pub bits: u32,
+}
+
+/// Proves that a transaction is part of the block with a given hash, without
+/// needing the rest of the block.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InclusionProof {
+    pub header: Header,
+    pub path: MerkleProof,
 }
 
 impl Block {
-    pub fn new(prev: Block, data: &str) -> Self {
+    pub fn new(prev: Block, transactions: Vec<Transaction>, memo: Option<&str>) -> Self {
         Block {
             timestamp: time(),
             nonce: 0,
             prev: prev.hash(),
             height: prev.height + 1,
-            data: data.to_string(),
-            difficulty: prev.difficulty,
+            merkle_root: Block::merkle(&transactions),
+            transactions,
+            memo: memo.map(|m| m.to_string()),
+            bits: prev.bits,
         }
     }
 
@@ -31,19 +75,248 @@ impl Block {
             nonce: 0,
             prev: Hash::blank(),
             height: 0,
-            data: "".to_string(),
-            difficulty: 5,
+            merkle_root: Hash::blank(),
+            transactions: Vec::new(),
+            memo: None,
+            bits: GENESIS_BITS,
+        }
+    }
+
+    /// Merkle root over the hashes of `transactions`.
+    pub fn merkle(transactions: &[Transaction]) -> Hash {
+        let leaves: Vec<Hash> = transactions.iter().map(|t| t.txid()).collect();
+        merkle_root(&leaves)
+    }
+
+    pub fn header(&self) -> Header {
+        Header {
+            version: HEADER_VERSION,
+            timestamp: self.timestamp,
+            nonce: self.nonce,
+            prev: self.prev.clone(),
+            height: self.height,
+            merkle_root: self.merkle_root.clone(),
+            memo: match &self.memo {
+                Some(memo) => Hash::new(memo).unwrap(),
+                None => Hash::blank(),
+            },
+            bits: self.bits,
+        }
+    }
+
+    pub fn hash(&self) -> Hash {
+        self.header().hash()
+    }
+
+    /// Whether the block hash, read as a number, is at most the target in `bits`.
+    pub fn proof(&self) -> bool {
+        target::meets(digest(self.header().encode()), self.bits)
+    }
+
+    /// Inclusion proof for the transaction with hash `tx`, if it is in this block.
+    pub fn prove(&self, tx: &Hash) -> Option<InclusionProof> {
+        let leaves: Vec<Hash> = self.transactions.iter().map(|t| t.txid()).collect();
+        let index = leaves.iter().position(|l| l == tx)?;
+
+        Some(InclusionProof {
+            header: self.header(),
+            path: merkle_proof(&leaves, index)?,
+        })
+    }
+}
+
+impl Header {
+    /// Encodes the header as
+    ///
+    /// ```text
+    /// version: u32 | prev: [u8; 32] | merkle_root: [u8; 32] | memo: [u8; 32]
+    /// | height: u64 | timestamp: u64 | bits: u32 | nonce: u64
+    /// ```
+    ///
+    /// with integers in little-endian order, so every header has exactly one
+    /// encoding and no two headers share one. The hashes must be valid
+    /// digests, which deserializing a block or header guarantees.
+    pub fn encode(&self) -> [u8; HEADER_SIZE] {
+        let mut out = [0; HEADER_SIZE];
+        let fields: [&[u8]; 8] = [
+            &self.version.to_le_bytes(),
+            &digest_bytes(&self.prev),
+            &digest_bytes(&self.merkle_root),
+            &digest_bytes(&self.memo),
+            &(self.height as u64).to_le_bytes(),
+            &self.timestamp.to_le_bytes(),
+            &self.bits.to_le_bytes(),
+            &(self.nonce as u64).to_le_bytes(),
+        ];
+
+        let mut i = 0;
+        for field in fields {
+            out[i..i + field.len()].copy_from_slice(field);
+            i += field.len();
+        }
+
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Header, String> {
+        if bytes.len() != HEADER_SIZE {
+            return Err(format!("Header must be {} bytes long.", HEADER_SIZE));
+        }
+
+        let version = u32::from_le_bytes(bytes[..4].try_into().unwrap());
+        if version != HEADER_VERSION {
+            return Err(format!("Unknown header version {}.", version));
         }
+
+        let hash = |i: usize| Hash::from_bytes(bytes[i..i + 32].try_into().unwrap());
+        let int = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+
+        Ok(Header {
+            version,
+            prev: hash(4),
+            merkle_root: hash(36),
+            memo: hash(68),
+            height: int(100) as usize,
+            timestamp: int(108),
+            bits: u32::from_le_bytes(bytes[116..120].try_into().unwrap()),
+            nonce: int(120) as usize,
+        })
     }
 
     pub fn hash(&self) -> Hash {
-        Hash::new(
-            format!(
-                "{}{}{}{}{}{}",
-                self.timestamp, self.prev.0, self.nonce, self.data, self.height, self.difficulty
+        Hash::new(self.encode()).unwrap()
+    }
+}
+
+fn digest_bytes(hash: &Hash) -> [u8; 32] {
+    hash.to_bytes().expect("header hashes are valid digests")
+}
+
+/// Headers travel as the hex of their binary encoding.
+impl Serialize for Header {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(self.encode()))
+    }
+}
+
+impl<'de> Deserialize<'de> for Header {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        let bytes = hex::decode(s).map_err(de::Error::custom)?;
+        Header::decode(&bytes).map_err(de::Error::custom)
+    }
+}
+
+impl InclusionProof {
+    /// Whether the transaction with hash `tx` is in the block with hash `block`.
+    pub fn verify(&self, tx: &Hash, block: &Hash) -> bool {
+        &self.header.hash() == block
+            && self.path.root(tx).as_ref() == Some(&self.header.merkle_root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> Header {
+        Header {
+            version: HEADER_VERSION,
+            timestamp: 1_700_000_000,
+            nonce: 12,
+            prev: Hash::new("prev").unwrap(),
+            height: 3,
+            merkle_root: Hash::new("root").unwrap(),
+            memo: Hash::new("3").unwrap(),
+            bits: GENESIS_BITS,
+        }
+    }
+
+    // ✅ Test: Known encodings and hashes
+    #[test]
+    fn vectors() {
+        let genesis = Block::genesis().header();
+        assert_eq!(
+            hex::encode(genesis.encode()),
+            concat!(
+                "02000000",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "0000000000000000",
+                "0000000000000000",
+                "ffff0720",
+                "0000000000000000",
             )
-            .as_str(),
-        )
-        .unwrap()
+        );
+        assert_eq!(
+            genesis.hash().0,
+            "80b8f0473cc5dfc9a0f23c470ef8f8f6ba8667b8cf4103009617c25ec0f559e7"
+        );
+
+        let h = header();
+        assert_eq!(
+            hex::encode(h.encode()),
+            concat!(
+                "02000000",
+                "84fd9bac333ad79154348296204fa7f8c537a96e08983e5f73b3f5aca8e8edf7",
+                "4813494d137e1631bba301d5acab6e7bb7aa74ce1185d456565ef51d737677b2",
+                "4e07408562bedb8b60ce05c1decfe3ad16b72230967de01f640b7e4729b49fce",
+                "0300000000000000",
+                "00f1536500000000",
+                "ffff0720",
+                "0c00000000000000",
+            )
+        );
+        assert_eq!(
+            h.hash().0,
+            "b9f40a7c5fe08521d0d9f078e608b6477a3380f9d3ab1be2fce0d0126e5a2289"
+        );
+    }
+
+    #[test]
+    fn roundtrip() {
+        let h = header();
+
+        assert_eq!(Header::decode(&h.encode()), Ok(h.clone()));
+        assert_eq!(
+            serde_json::from_value::<Header>(serde_json::json!(h)).unwrap(),
+            h
+        );
+    }
+
+    // ✅ Test: Moving digits between fields changes the hash
+    #[test]
+    fn unambiguous() {
+        let mut a = header();
+        let mut b = header();
+        a.nonce = 12;
+        a.height = 3;
+        b.nonce = 1;
+        b.height = 23;
+
+        assert_ne!(a.hash(), b.hash());
+    }
+
+    #[test]
+    fn invalid() {
+        let mut bytes = header().encode();
+        assert!(Header::decode(&bytes[1..]).is_err());
+
+        bytes[0] = 1;
+        assert!(Header::decode(&bytes).is_err());
+    }
+
+    // ✅ Test: Blocks whose hashes are not digests are rejected, not encoded as zeros
+    #[test]
+    fn malformed_hashes() {
+        let block = serde_json::json!(Block::genesis());
+        assert!(serde_json::from_value::<Block>(block.clone()).is_ok());
+
+        for (field, hash) in [("prev", "zz"), ("prev", "00"), ("merkle_root", "not hex")] {
+            let mut b = block.clone();
+            b[field] = serde_json::json!(hash);
+            assert!(serde_json::from_value::<Block>(b).is_err());
+        }
     }
 }
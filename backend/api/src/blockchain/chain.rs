@@ -1,120 +1,373 @@
-use std::time::Instant;
-
-use crate::{blockchain::block::Block, utils::time};
-use serde::{Deserialize, Serialize};
-use to_binary::BinaryString;
-
-const MINE_RATE: u64 = 1;
-
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+use std::{collections::HashSet, sync::atomic::AtomicBool};
+
+use secp256k1::PublicKey;
+use serde::Serialize;
+
+use crate::{
+    blockchain::{
+        block::Block,
+        difficulty::{DifficultyAlgorithm, Retarget, TARGET_BLOCK_TIME},
+        error::{InvalidBlock, Rule},
+        fork::{ForkChoice, MostWork},
+        store::{MemoryStore, Store},
+        utxo::{Undo, UtxoSet},
+    },
+    miner::Miner,
+    transaction::{
+        amount::Amount,
+        transaction::{mining_reward, Transaction, DEFAULT_CHAIN_ID},
+    },
+    utils::{hash::Hash, time},
+};
+
+/// Number of preceding blocks whose median timestamp a new block must not fall behind.
+const MEDIAN_SPAN: usize = 11;
+/// How far ahead of our own clock a block's timestamp may be, in seconds.
+const MAX_FUTURE: u64 = 2 * 60 * 60;
+/// Largest memo a block may carry, in bytes.
+const MAX_MEMO: usize = 256;
+
+#[derive(Debug)]
 pub struct Chain {
     pub chain: Vec<Block>,
+    store: Box<dyn Store>,
+    fork: Box<dyn ForkChoice>,
+    difficulty: Box<dyn DifficultyAlgorithm>,
+    utxos: UtxoSet,
+    /// Coins spent by each block in `chain`, to undo it in a reorganisation.
+    undo: Vec<Undo>,
 }
 
 impl Chain {
     pub fn new() -> Self {
-        let mut chain = Vec::new();
-        chain.push(Block::genesis());
-        Chain { chain }
+        Chain::open(
+            Box::new(MemoryStore::new()),
+            Box::new(Retarget::new(TARGET_BLOCK_TIME)),
+            DEFAULT_CHAIN_ID,
+        )
+        .unwrap()
     }
 
-    pub fn add(&mut self, data: &str) -> Block {
-        let b = self.mine(data);
-        self.push(b.clone());
-        b
+    /// Loads the chain from `store`, writing the genesis block if it is empty.
+    ///
+    /// Stored blocks are re-validated on the way in, rebuilding the UTXO set,
+    /// and the store is cut back to the last valid block, so a half-written
+    /// or corrupted tail left by a crash is discarded rather than adopted.
+    /// Transactions must be signed for the network `chain_id`.
+    pub fn open(
+        mut store: Box<dyn Store>,
+        difficulty: Box<dyn DifficultyAlgorithm>,
+        chain_id: u32,
+    ) -> Result<Self, String> {
+        let stored = store.load()?;
+
+        if stored.is_empty() {
+            store.append(&Block::genesis())?;
+        } else if stored[0] != Block::genesis() {
+            return Err("Stored genesis block does not match.".to_owned());
+        }
+
+        let mut c = Chain {
+            chain: vec![Block::genesis()],
+            store,
+            fork: Box::new(MostWork),
+            difficulty,
+            utxos: UtxoSet::new(chain_id),
+            undo: vec![Undo::default()],
+        };
+
+        for (i, block) in stored.into_iter().enumerate().skip(1) {
+            let connected = c
+                .check_block(c.tail(i), &block)
+                .and_then(|_| c.utxos.connect(&block));
+
+            match connected {
+                Ok(undo) => {
+                    c.chain.push(block);
+                    c.undo.push(undo);
+                }
+                Err(_) => {
+                    eprintln!(
+                        "⚠️ Warning: stored chain is invalid from height {}, truncating.",
+                        i
+                    );
+                    c.store.truncate(i)?;
+                    break;
+                }
+            }
+        }
+
+        Ok(c)
     }
 
-    fn push(&mut self, block: Block) {
-        self.chain.push(block);
+    /// Replaces the default most-work fork choice rule used by `replace`.
+    pub fn with_fork_choice(mut self, fork: Box<dyn ForkChoice>) -> Self {
+        self.fork = fork;
+        self
+    }
+
+    /// Mines a block on the calling thread and appends it.
+    pub fn add(
+        &mut self,
+        miner: &PublicKey,
+        transactions: Vec<Transaction>,
+        memo: Option<&str>,
+    ) -> Result<Block, String> {
+        let template = self.template(miner, transactions, memo);
+        let b = Miner::new(1)
+            .mine(&template, &AtomicBool::new(false))
+            .unwrap();
+        self.push(b.clone())?;
+        Ok(b)
     }
 
-    fn pop(&mut self) {
-        self.chain.pop();
+    /// Block building on the current tip at the required difficulty, for a
+    /// `Miner` to find the nonce of. It starts with a coinbase paying the
+    /// mining reward and the fees of `transactions` to `miner`.
+    pub fn template(
+        &self,
+        miner: &PublicKey,
+        transactions: Vec<Transaction>,
+        memo: Option<&str>,
+    ) -> Block {
+        let fees = Amount::sum(transactions.iter().filter_map(|t| self.utxos.fee(t)));
+        let subsidy = mining_reward(self.chain.len());
+        let reward = fees.and_then(|f| f.checked_add(subsidy)).unwrap_or(subsidy);
+        let coinbase = Transaction::reward(miner, reward, self.chain.len());
+
+        let transactions = [vec![coinbase], transactions].concat();
+        let mut block = Block::new(self.chain.last().unwrap().clone(), transactions, memo);
+        block.bits = self.next_bits();
+        block
     }
 
-    fn mine(&mut self, data: &str) -> Block {
-        let b = self.chain.last().unwrap();
+    /// Compact target the next block must meet.
+    pub fn next_bits(&self) -> u32 {
+        self.difficulty.next(self.tail(self.chain.len()))
+    }
 
-        let mut block = Block::new(b.clone(), data);
+    /// Appends a block mined elsewhere, as long as it is valid and still builds on the tip.
+    pub fn submit(&mut self, block: Block) -> Result<(), String> {
+        self.check_block(self.tail(self.chain.len()), &block)
+            .map_err(|e| e.to_string())?;
+        self.push(block)
+    }
 
-        while hex_to_binary(block.hash().0.as_str())[..block.difficulty]
-            != "0".repeat(block.difficulty)
-        {
-            block.timestamp = time();
-
-            block.nonce += 1;
-
-            block.difficulty = {
-                let diff = b.difficulty;
-                if diff < 1 {
-                    1
-                } else if ((block.timestamp - b.timestamp) > MINE_RATE) {
-                    diff - 1
-                } else {
-                    diff + 1
-                }
-            };
+    /// The blocks below `height` needed to validate the block at `height`.
+    fn tail(&self, height: usize) -> &[Block] {
+        let span = MEDIAN_SPAN.max(self.difficulty.window() + 1);
+        &self.chain[height.saturating_sub(span)..height]
+    }
+
+    /// Outputs of the current chain that are still unspent.
+    /// Network the transactions of this chain are signed for.
+    pub fn chain_id(&self) -> u32 {
+        self.utxos.chain_id()
+    }
+
+    pub fn utxos(&self) -> &UtxoSet {
+        &self.utxos
+    }
+
+    /// Height of the block with the given hash, if it is part of this chain.
+    pub fn height_of(&self, hash: &Hash) -> Option<usize> {
+        self.store.height_of(hash)
+    }
+
+    fn push(&mut self, block: Block) -> Result<(), String> {
+        let undo = self.utxos.connect(&block).map_err(|e| e.to_string())?;
+
+        if let Err(e) = self.store.append(&block) {
+            self.utxos.disconnect(&block.transactions, undo);
+            return Err(e);
         }
 
-        block
+        self.chain.push(block);
+        self.undo.push(undo);
+        Ok(())
     }
 
-    fn check(chain: &Vec<Block>) -> Result<&str, &str> {
-        for (i, block) in chain.iter().enumerate() {
-            if i == 0 {
-                continue;
-            }
+    /// Checks `block` against the blocks preceding it, of which at least the
+    /// ones returned by `tail` must be given.
+    fn check_block(&self, chain: &[Block], block: &Block) -> Result<(), InvalidBlock> {
+        let prev = chain.last().unwrap();
+        let fail = |rule| Err(InvalidBlock::new(prev.height + 1, rule));
 
-            if block.prev != chain[i - 1].hash() {
-                return Err("Blockchain is not valid.");
-            }
+        if block.height != prev.height + 1 {
+            return fail(Rule::Height);
+        }
+
+        if block.prev != prev.hash() {
+            return fail(Rule::Prev);
+        }
+
+        let mut recent: Vec<u64> = chain
+            .iter()
+            .rev()
+            .take(MEDIAN_SPAN)
+            .map(|b| b.timestamp)
+            .collect();
+        recent.sort_unstable();
+
+        if block.timestamp < recent[recent.len() / 2] || block.timestamp > time() + MAX_FUTURE {
+            return fail(Rule::Timestamp);
         }
 
-        return Ok("Blockchain is valid.");
+        if block.bits != self.difficulty.next(chain) {
+            return fail(Rule::Difficulty);
+        }
+
+        if !Chain::check_body(block) {
+            return fail(Rule::Body);
+        }
+
+        if block.merkle_root != Block::merkle(&block.transactions) {
+            return fail(Rule::MerkleRoot);
+        }
+
+        if !block.proof() {
+            return fail(Rule::ProofOfWork);
+        }
+
+        Ok(())
     }
 
-    pub fn validate(&self) -> Result<&str, &str> {
-        Chain::check(&self.chain)
+    /// Checks that the block body is well formed: a memo of bounded size, no
+    /// repeated transactions, and transactions paying to at least one output
+    /// with a well-formed lock, without their total overflowing.
+    fn check_body(block: &Block) -> bool {
+        if block.memo.as_ref().is_some_and(|m| m.len() > MAX_MEMO) {
+            return false;
+        }
+
+        let mut ids = HashSet::new();
+
+        block.transactions.iter().all(|t| {
+            ids.insert(t.txid())
+                && !t.outputs.is_empty()
+                && Amount::sum(t.outputs.iter().map(|o| o.amount)).is_some()
+                && t.outputs.iter().all(|o| o.lock.check().is_ok())
+        })
     }
 
-    pub fn replace(&mut self, new_chain: Vec<Block>) -> Option<Vec<Block>> {
-        match Chain::check(&new_chain) {
-            Ok(_) => {
-                if new_chain.len() < self.chain.len() {
-                    return None;
-                }
+    fn check(&self, chain: &[Block]) -> Result<(), InvalidBlock> {
+        if chain.first() != Some(&Block::genesis()) {
+            return Err(InvalidBlock::new(0, Rule::Genesis));
+        }
 
-                if new_chain.len() == self.chain.len()
-                    && new_chain.last().unwrap().nonce <= self.chain.last().unwrap().nonce
-                {
-                    return None;
-                }
+        let mut utxos = UtxoSet::new(self.chain_id());
+        for i in 1..chain.len() {
+            self.check_block(&chain[..i], &chain[i])?;
+            utxos.connect(&chain[i])?;
+        }
 
-                self.chain = new_chain;
-                Some(self.chain.clone())
-            }
-            Err(_) => {
-                println!("new chain is not valid.");
-                None
-            }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), InvalidBlock> {
+        self.check(&self.chain)
+    }
+
+    /// Switches to `new_chain` if it shares our genesis block and the fork
+    /// choice rule prefers it over the current chain.
+    pub fn replace(&mut self, new_chain: Vec<Block>) -> Result<Reorg, String> {
+        if new_chain.first() != Some(&Block::genesis()) {
+            return Err(InvalidBlock::new(0, Rule::Genesis).to_string());
         }
+
+        self.reorg(new_chain)
     }
-}
 
-fn hex_to_binary(hex: &str) -> String {
-    match hex::decode(hex) {
-        Ok(bytes) => bytes
+    /// Switches to a competing `branch` that forks off somewhere in this chain.
+    ///
+    /// Leading blocks we already have are skipped, the first remaining block
+    /// must build on a block in this chain, and only the blocks past that
+    /// common ancestor are validated and compared by the fork choice rule.
+    pub fn reorg(&mut self, mut branch: Vec<Block>) -> Result<Reorg, String> {
+        let known = branch
+            .iter()
+            .take_while(|b| self.height_of(&b.hash()) == Some(b.height))
+            .count();
+        branch.drain(..known);
+
+        let first = branch.first().ok_or("Branch has no new blocks.")?;
+        let ancestor = self
+            .height_of(&first.prev)
+            .ok_or("Branch does not fork off this chain.")?;
+
+        // Blocks are validated against the tail of the chain before them.
+        let mut context = self.tail(ancestor + 1).to_vec();
+        for block in branch.iter() {
+            self.check_block(&context, block)
+                .map_err(|e| e.to_string())?;
+            context.push(block.clone());
+        }
+
+        if !self.fork.prefer(&self.chain[ancestor + 1..], &branch) {
+            return Err("Branch does not beat the current chain.".to_owned());
+        }
+
+        // Spends are checked on a copy of the coins as of the common ancestor.
+        let mut utxos = self.utxos.clone();
+        for (block, undo) in self.chain[ancestor + 1..]
             .iter()
-            .map(|byte| format!("{:08b}", byte)) // Convert each byte to 8-bit binary
-            .collect::<Vec<String>>()
-            .join(""),
-        Err(e) => format!("Error decoding hex: {}", e),
+            .zip(&self.undo[ancestor + 1..])
+            .rev()
+        {
+            utxos.disconnect(&block.transactions, undo.clone());
+        }
+
+        let mut undo = Vec::new();
+        for block in branch.iter() {
+            undo.push(utxos.connect(block).map_err(|e| e.to_string())?);
+        }
+
+        // If the store fails half way, it goes back to the blocks we keep, so
+        // that it still matches the chain in memory.
+        if let Err(e) = self.store.replace(ancestor + 1, &branch) {
+            self.store
+                .replace(ancestor + 1, &self.chain[ancestor + 1..])
+                .map_err(|r| format!("{} Could not restore the store: {}", e, r))?;
+            return Err(e);
+        }
+
+        let disconnected = self.chain.split_off(ancestor + 1);
+        self.chain.extend(branch.iter().cloned());
+        self.undo.truncate(ancestor + 1);
+        self.undo.extend(undo);
+        self.utxos = utxos;
+
+        Ok(Reorg {
+            disconnected,
+            connected: branch,
+        })
     }
 }
 
+/// Blocks removed from and added to the chain by a reorganisation.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Reorg {
+    /// Blocks that are no longer part of the chain, in height order.
+    pub disconnected: Vec<Block>,
+    /// Blocks that now follow the common ancestor, in height order.
+    pub connected: Vec<Block>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        blockchain::{store::FileStore, target},
+        transaction::{
+            output::Lock,
+            script::{Op, Script},
+            transaction::INITIAL_REWARD,
+            wallet::Wallet,
+        },
+        utils::uint::U256,
+    };
+    use std::{env::temp_dir, fs};
+    use uuid::Uuid;
 
     static DATA: &str = "foo";
     static DATA2: &str = "goo";
@@ -123,6 +376,10 @@ mod tests {
         (Chain::new(), Chain::new())
     }
 
+    fn miner() -> PublicKey {
+        Wallet::new().public
+    }
+
     // ✅ Test: Blockchain starts with the Genesis block
     #[test]
     fn genesis() {
@@ -133,38 +390,36 @@ mod tests {
     #[test]
     fn add() {
         let mut c = create().0;
-        c.add(DATA);
+        c.add(&miner(), Vec::new(), Some(DATA)).unwrap();
 
-        assert_eq!(c.chain.last().unwrap().data, DATA);
+        assert_eq!(c.chain.last().unwrap().memo.as_deref(), Some(DATA));
     }
 
     // ✅ Test: Validates a valid chain
     #[test]
     fn valid() {
         let mut c: Chain = create().0;
-        c.add(DATA);
+        c.add(&miner(), Vec::new(), Some(DATA)).unwrap();
 
-        assert_eq!(c.validate().unwrap(), "Blockchain is valid.");
+        assert!(c.validate().is_ok());
     }
 
     #[test]
     fn verify_nonce() {
         let mut c: Chain = create().0;
-        c.add(DATA);
+        c.add(&miner(), Vec::new(), Some(DATA)).unwrap();
 
         let b = c.chain.last().unwrap();
+        let hash = U256::from_be_bytes(b.hash().to_bytes().unwrap());
 
-        assert_eq!(
-            hex_to_binary(b.hash().0.as_str())[..b.difficulty],
-            "0".repeat(b.difficulty)
-        );
+        assert!(hash <= target::from_compact(b.bits));
     }
     // ✅ Test: Invalidates a chain with a corrupt Genesis block
     #[test]
     fn invalid_genesis() {
         let mut c: Chain = create().0;
 
-        c.chain[0].data = "Bad data".to_string(); // ❌ Corrupt Genesis Block
+        c.chain[0].memo = Some("Bad data".to_string()); // ❌ Corrupt Genesis Block
 
         assert_ne!(c.chain[0].hash(), Block::genesis().hash());
     }
@@ -174,11 +429,230 @@ mod tests {
     fn invalid_chain() {
         let (c1, mut c2) = create();
 
-        c2.add(DATA);
-        c2.chain[0].data = "Not foo".to_string(); // ❌ Corrupt Data in Block
+        c2.add(&miner(), Vec::new(), Some(DATA)).unwrap();
+        c2.chain[0].memo = Some("Not foo".to_string()); // ❌ Corrupt Data in Block
 
         assert_ne!(c1.validate(), c2.validate());
-        assert_eq!(Chain::check(&c2.chain), Err("Blockchain is not valid."));
+        assert_eq!(
+            c2.check(&c2.chain),
+            Err(InvalidBlock::new(0, Rule::Genesis))
+        );
+    }
+
+    // ✅ Test: Each consensus rule is reported against the offending block
+    #[test]
+    fn invalid_rules() {
+        let mut c = create().0;
+        c.add(&miner(), Vec::new(), Some(DATA)).unwrap();
+        c.add(&miner(), Vec::new(), Some(DATA2)).unwrap();
+
+        let fails = |f: &dyn Fn(&mut Block)| {
+            let mut chain = c.chain.clone();
+            f(&mut chain[2]);
+            c.check(&chain).unwrap_err()
+        };
+
+        assert_eq!(fails(&|b| b.height = 5), InvalidBlock::new(2, Rule::Height));
+        assert_eq!(
+            fails(&|b| b.prev = Hash::blank()),
+            InvalidBlock::new(2, Rule::Prev)
+        );
+        assert_eq!(
+            fails(&|b| b.timestamp = 0),
+            InvalidBlock::new(2, Rule::Timestamp)
+        );
+        assert_eq!(
+            fails(&|b| b.timestamp = time() + MAX_FUTURE + 60),
+            InvalidBlock::new(2, Rule::Timestamp)
+        );
+        assert_eq!(
+            fails(&|b| b.bits -= 1),
+            InvalidBlock::new(2, Rule::Difficulty)
+        );
+        assert_eq!(
+            fails(&|b| b.memo = Some("x".repeat(MAX_MEMO + 1))),
+            InvalidBlock::new(2, Rule::Body)
+        );
+        assert_eq!(
+            fails(&|b| {
+                b.transactions[0].outputs[0].lock = Lock::Multisig {
+                    threshold: 0,
+                    keys: Vec::new(),
+                }
+            }),
+            InvalidBlock::new(2, Rule::Body)
+        );
+        assert_eq!(
+            fails(&|b| b.transactions[0].outputs[0].lock = Lock::Script(Script(vec![Op::If]))),
+            InvalidBlock::new(2, Rule::Body)
+        );
+        assert_eq!(
+            fails(&|b| b.transactions = vec![Transaction::reward(&miner(), INITIAL_REWARD, 2); 2]),
+            InvalidBlock::new(2, Rule::Body)
+        );
+        assert_eq!(
+            fails(&|b| b.transactions = vec![Transaction::reward(&miner(), INITIAL_REWARD, 2)]),
+            InvalidBlock::new(2, Rule::MerkleRoot)
+        );
+        assert_eq!(
+            fails(&|b| while b.proof() {
+                b.nonce += 1
+            }),
+            InvalidBlock::new(2, Rule::ProofOfWork)
+        );
+    }
+
+    // ✅ Test: A spend signed for one network is rejected by another with the same coins
+    #[test]
+    fn chain_id() {
+        let w = Wallet::new();
+        let mut dev = Chain::new();
+        let mut staging = Chain::open(
+            Box::new(MemoryStore::new()),
+            Box::new(Retarget::new(TARGET_BLOCK_TIME)),
+            DEFAULT_CHAIN_ID + 1,
+        )
+        .unwrap();
+
+        dev.add(&w.public, Vec::new(), None).unwrap();
+        staging.add(&w.public, Vec::new(), None).unwrap();
+
+        let coins = dev.utxos().owned_by(&w.public);
+        assert_eq!(coins, staging.utxos().owned_by(&w.public));
+
+        let spend = Transaction::new(
+            &w,
+            &coins,
+            &miner(),
+            Amount::from_coins(10),
+            Amount::ZERO,
+            dev.chain_id(),
+        )
+        .unwrap();
+        assert!(spend.verify(staging.chain_id()).is_err());
+
+        assert!(staging.add(&miner(), vec![spend.clone()], None).is_err());
+        dev.add(&miner(), vec![spend], None).unwrap();
+    }
+
+    // ✅ Test: Blocks whose transactions break consensus are rejected even when mined
+    #[test]
+    fn invalid_transactions() {
+        let mut c = create().0;
+        let w = Wallet::new();
+        c.add(&w.public, Vec::new(), None).unwrap();
+
+        let coins = c.utxos().owned_by(&w.public);
+        let spend = Transaction::new(
+            &w,
+            &coins,
+            &miner(),
+            Amount::from_coins(10),
+            Amount::ZERO,
+            DEFAULT_CHAIN_ID,
+        )
+        .unwrap();
+
+        let fails = |f: &dyn Fn(&mut Vec<Transaction>)| {
+            let mut b = c.template(&miner(), vec![spend.clone()], None);
+            f(&mut b.transactions);
+            b.merkle_root = Block::merkle(&b.transactions);
+
+            let mut chain = c.chain.clone();
+            chain.push(Miner::new(1).mine(&b, &AtomicBool::new(false)).unwrap());
+            c.check(&chain)
+        };
+
+        assert_eq!(fails(&|_| {}), Ok(()));
+        assert_eq!(
+            fails(&|t| t.truncate(0)),
+            Err(InvalidBlock::new(2, Rule::Coinbase))
+        );
+        assert_eq!(
+            fails(&|t| t.swap(0, 1)),
+            Err(InvalidBlock::new(2, Rule::Coinbase))
+        );
+        assert_eq!(
+            fails(&|t| t.push(Transaction::reward(&miner(), INITIAL_REWARD, 2))),
+            Err(InvalidBlock::new(2, Rule::Coinbase))
+        );
+        assert_eq!(
+            fails(&|t| t[0] = c.chain[1].transactions[0].clone()),
+            Err(InvalidBlock::new(2, Rule::Coinbase))
+        );
+        assert_eq!(
+            fails(&|t| t[0].outputs[0].amount = Amount::from_coins(51)),
+            Err(InvalidBlock::new(2, Rule::Coinbase))
+        );
+        assert_eq!(
+            fails(&|t| t[1].outputs[1].amount = Amount::from_coins(11)),
+            Err(InvalidBlock::new(2, Rule::Signature))
+        );
+        assert_eq!(
+            fails(&|t| t.push(spend.clone())),
+            Err(InvalidBlock::new(2, Rule::Body))
+        );
+        assert_eq!(
+            fails(&|t| {
+                let double = Transaction::new(
+                    &w,
+                    &coins,
+                    &w.public,
+                    Amount::from_coins(10),
+                    Amount::ZERO,
+                    DEFAULT_CHAIN_ID,
+                );
+                t.push(double.unwrap());
+            }),
+            Err(InvalidBlock::new(2, Rule::Inputs))
+        );
+        assert_eq!(
+            fails(&|t| {
+                t[1].outputs[1].amount = Amount::from_coins(11);
+                t[1].sign(&w, DEFAULT_CHAIN_ID).unwrap();
+            }),
+            Err(InvalidBlock::new(2, Rule::Balance))
+        );
+    }
+
+    // ✅ Test: Transactions can be proven against the block hash alone
+    #[test]
+    fn inclusion_proof() {
+        let mut c = create().0;
+        let wallets: Vec<Wallet> = (0..3).map(|_| Wallet::new()).collect();
+        for w in wallets.iter() {
+            c.add(&w.public, Vec::new(), None).unwrap();
+        }
+
+        let txs: Vec<Transaction> = wallets
+            .iter()
+            .map(|w| {
+                let coins = c.utxos().owned_by(&w.public);
+                Transaction::new(
+                    w,
+                    &coins,
+                    &miner(),
+                    Amount::from_coins(1),
+                    Amount::ZERO,
+                    DEFAULT_CHAIN_ID,
+                )
+                .unwrap()
+            })
+            .collect();
+
+        let b = c.add(&miner(), txs.clone(), None).unwrap();
+
+        for t in txs.iter() {
+            let proof = b.prove(&t.txid()).unwrap();
+            assert!(proof.verify(&t.txid(), &b.hash()));
+            assert!(!proof.verify(&t.txid(), &c.chain[0].hash()));
+            assert!(!proof.verify(&Hash::blank(), &b.hash()));
+            assert!(!proof.verify(&Hash("not hex".to_string()), &b.hash()));
+        }
+
+        assert!(b
+            .prove(&Transaction::reward(&miner(), INITIAL_REWARD, b.height).txid())
+            .is_none());
     }
 
     // ✅ Test: Replaces the chain with a valid chain
@@ -186,18 +660,185 @@ mod tests {
     fn replace_chain() {
         let (mut c1, mut c2) = create();
 
-        c2.add(DATA2);
-        assert!(c1.replace(c2.chain.clone()).is_some()); // ✅ Replacement should succeed
+        c2.add(&miner(), Vec::new(), Some(DATA2)).unwrap();
+        assert!(c1.replace(c2.chain.clone()).is_ok()); // ✅ Replacement should succeed
         assert_eq!(c1.chain.len(), 2); // ✅ New chain has length 2
     }
 
     // ✅ Test: Does not replace chain if it's shorter or equal
     #[test]
     fn replace_chain_invalid_length() {
-        let (mut c1, mut c2) = create();
+        let (mut c1, c2) = create();
 
-        c1.add(DATA); // ✅ Original chain has length 2
-        assert!(c1.replace(c2.chain.clone()).is_none()); // ❌ Replacement should fail
+        c1.add(&miner(), Vec::new(), Some(DATA)).unwrap(); // ✅ Original chain has length 2
+        assert!(c1.replace(c2.chain.clone()).is_err()); // ❌ Replacement should fail
         assert_eq!(c1.chain.len(), 2); // ✅ Chain length should remain unchanged
     }
+
+    // ✅ Test: Does not replace chain with an identical one
+    #[test]
+    fn replace_chain_same() {
+        let mut c = create().0;
+        c.add(&miner(), Vec::new(), Some(DATA)).unwrap();
+
+        assert!(c.replace(c.chain.clone()).is_err());
+    }
+
+    // ✅ Test: Reorganises onto a heavier branch from the common ancestor
+    #[test]
+    fn reorg() {
+        let (mut c1, mut c2) = create();
+
+        let orphan = c1.add(&miner(), Vec::new(), Some(DATA)).unwrap();
+        c2.add(&miner(), Vec::new(), Some(DATA2)).unwrap();
+        c2.add(&miner(), Vec::new(), Some(DATA2)).unwrap();
+
+        let r = c1.reorg(c2.chain[1..].to_vec()).unwrap();
+        assert_eq!(r.disconnected, vec![orphan]);
+        assert_eq!(r.connected, c2.chain[1..]);
+        assert_eq!(c1.chain, c2.chain);
+
+        // ✅ Already known blocks are skipped, leaving nothing to connect
+        assert!(c1.reorg(c2.chain.clone()).is_err());
+    }
+
+    // ✅ Test: Coins created by orphaned blocks are gone after a reorg
+    #[test]
+    fn reorg_utxos() {
+        let (mut c1, mut c2) = create();
+        let mut w = Wallet::new();
+
+        c1.add(&w.public, Vec::new(), None).unwrap();
+        assert_eq!(w.calculate_balance(&c1), Amount::from_coins(50));
+
+        c2.add(&miner(), Vec::new(), Some(DATA2)).unwrap();
+        c2.add(&miner(), Vec::new(), Some(DATA2)).unwrap();
+        c1.reorg(c2.chain[1..].to_vec()).unwrap();
+
+        assert_eq!(w.calculate_balance(&c1), Amount::ZERO);
+        assert!(c1.validate().is_ok());
+    }
+
+    // ✅ Test: Rejects a branch whose ancestor is unknown or whose blocks are invalid
+    #[test]
+    fn reorg_invalid() {
+        let (mut c1, mut c2) = create();
+
+        c2.add(&miner(), Vec::new(), Some(DATA2)).unwrap();
+        c2.add(&miner(), Vec::new(), Some(DATA2)).unwrap();
+        assert!(c1.reorg(c2.chain[2..].to_vec()).is_err());
+
+        let mut branch = c2.chain[1..].to_vec();
+        branch[1].height = 7;
+        assert!(c1.reorg(branch).is_err());
+        assert_eq!(c1.chain.len(), 1);
+    }
+
+    /// Store whose `n`th append fails.
+    #[derive(Debug)]
+    struct FailingStore {
+        blocks: MemoryStore,
+        appends: usize,
+        n: usize,
+    }
+
+    impl Store for FailingStore {
+        fn load(&mut self) -> Result<Vec<Block>, String> {
+            self.blocks.load()
+        }
+
+        fn append(&mut self, block: &Block) -> Result<(), String> {
+            self.appends += 1;
+            match self.appends == self.n {
+                true => Err("disk full.".to_owned()),
+                false => self.blocks.append(block),
+            }
+        }
+
+        fn truncate(&mut self, height: usize) -> Result<(), String> {
+            self.blocks.truncate(height)
+        }
+
+        fn height_of(&self, hash: &Hash) -> Option<usize> {
+            self.blocks.height_of(hash)
+        }
+    }
+
+    // ✅ Test: A reorg the store fails to write leaves both on the old branch
+    #[test]
+    fn reorg_store_failure() {
+        // The genesis block, our own block and the first of the branch get written
+        let store = FailingStore {
+            blocks: MemoryStore::new(),
+            appends: 0,
+            n: 4,
+        };
+        let mut c1 = Chain::open(
+            Box::new(store),
+            Box::new(Retarget::new(TARGET_BLOCK_TIME)),
+            DEFAULT_CHAIN_ID,
+        )
+        .unwrap();
+        let mut c2 = Chain::new();
+
+        c1.add(&miner(), Vec::new(), Some(DATA)).unwrap();
+        c2.add(&miner(), Vec::new(), Some(DATA2)).unwrap();
+        c2.add(&miner(), Vec::new(), Some(DATA2)).unwrap();
+
+        let before = c1.chain.clone();
+        assert!(c1.reorg(c2.chain[1..].to_vec()).is_err());
+        assert_eq!(c1.chain, before);
+        assert_eq!(c1.store.load().unwrap(), before);
+    }
+
+    // ✅ Test: Blocks and replacements survive reopening the store
+    #[test]
+    fn persist() {
+        let dir = temp_dir().join(format!("chain-{}", Uuid::new_v4()));
+        let open = || {
+            Chain::open(
+                Box::new(FileStore::open(&dir).unwrap()),
+                Box::new(Retarget::new(TARGET_BLOCK_TIME)),
+                DEFAULT_CHAIN_ID,
+            )
+            .unwrap()
+        };
+
+        let mut c1 = open();
+        c1.add(&miner(), Vec::new(), Some(DATA)).unwrap();
+
+        let mut c2 = Chain::new();
+        c2.add(&miner(), Vec::new(), Some(DATA2)).unwrap();
+        c2.add(&miner(), Vec::new(), Some(DATA2)).unwrap();
+        assert!(c1.replace(c2.chain.clone()).is_ok());
+        drop(c1);
+
+        let c1 = open();
+        assert_eq!(c1.chain, c2.chain);
+        assert_eq!(c1.height_of(&c2.chain[2].hash()), Some(2));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    // ✅ Test: An invalid stored tail is dropped on open
+    #[test]
+    fn recover_tail() {
+        let mut store = MemoryStore::new();
+        let mut c = Chain::new();
+        c.add(&miner(), Vec::new(), Some(DATA)).unwrap();
+        c.add(&miner(), Vec::new(), Some(DATA2)).unwrap();
+        c.chain[2].prev = Hash::blank(); // ❌ Break the link to the tail block
+
+        for b in c.chain.iter() {
+            store.append(b).unwrap();
+        }
+
+        let c = Chain::open(
+            Box::new(store),
+            Box::new(Retarget::new(TARGET_BLOCK_TIME)),
+            DEFAULT_CHAIN_ID,
+        )
+        .unwrap();
+        assert_eq!(c.chain.len(), 2);
+    }
 }
@@ -0,0 +1,173 @@
+use std::fmt::Debug;
+
+use crate::{
+    blockchain::{block::Block, target},
+    utils::uint::U256,
+};
+
+/// Default number of blocks between retargets.
+pub const RETARGET_WINDOW: usize = 10;
+/// Default number of blocks the LWMA averages over.
+pub const LWMA_WINDOW: usize = 30;
+/// Default number of seconds a block should take to mine.
+pub const TARGET_BLOCK_TIME: u64 = 10;
+
+/// Rule deciding the target of the next block from the ones before it.
+pub trait DifficultyAlgorithm: Send + Debug {
+    /// How many of the most recent blocks `next` looks at.
+    fn window(&self) -> usize;
+
+    /// Compact target the block following `chain` must have. `chain` ends at
+    /// the current tip and holds at least the last `window() + 1` blocks, or
+    /// the whole chain if it is shorter.
+    fn next(&self, chain: &[Block]) -> u32;
+}
+
+/// Keeps the target fixed for `window` blocks, then scales it by how long the
+/// window took to mine compared to how long it should have, by at most a
+/// factor of four either way.
+#[derive(Debug, Clone)]
+pub struct Retarget {
+    pub window: usize,
+    pub target: u64,
+}
+
+impl Retarget {
+    pub fn new(target: u64) -> Self {
+        Retarget {
+            window: RETARGET_WINDOW,
+            target,
+        }
+    }
+}
+
+impl DifficultyAlgorithm for Retarget {
+    fn window(&self) -> usize {
+        self.window
+    }
+
+    fn next(&self, chain: &[Block]) -> u32 {
+        let tip = chain.last().unwrap();
+
+        if !(tip.height + 1).is_multiple_of(self.window) || chain.len() <= self.window {
+            return tip.bits;
+        }
+
+        let first = &chain[chain.len() - 1 - self.window];
+        let expected = self.target * self.window as u64;
+        let actual = tip
+            .timestamp
+            .saturating_sub(first.timestamp)
+            .clamp(expected / 4, expected * 4)
+            .max(1);
+
+        let next = target::from_compact(tip.bits).mul_div(actual, expected);
+        target::to_compact(next.min(target::pow_limit()))
+    }
+}
+
+/// Linearly weighted moving average over the last `window` solve times, where
+/// recent blocks count the most, adjusting the target every block.
+#[derive(Debug, Clone)]
+pub struct Lwma {
+    pub window: usize,
+    pub target: u64,
+}
+
+impl Lwma {
+    pub fn new(target: u64) -> Self {
+        Lwma {
+            window: LWMA_WINDOW,
+            target,
+        }
+    }
+}
+
+impl DifficultyAlgorithm for Lwma {
+    fn window(&self) -> usize {
+        self.window
+    }
+
+    fn next(&self, chain: &[Block]) -> u32 {
+        let tip = chain.last().unwrap();
+        let n = self.window.min(chain.len() - 1);
+
+        if n == 0 {
+            return tip.bits;
+        }
+
+        let blocks = &chain[chain.len() - 1 - n..];
+        let mut weighted: u64 = 0;
+        let mut average = U256::ZERO;
+
+        for (i, pair) in blocks.windows(2).enumerate() {
+            // Solve times are clamped so a single bad timestamp cannot swing it too far.
+            let solve = pair[1]
+                .timestamp
+                .saturating_sub(pair[0].timestamp)
+                .clamp(1, 6 * self.target);
+
+            weighted += (i as u64 + 1) * solve;
+            average = average + target::from_compact(pair[1].bits).mul_div(1, n as u64);
+        }
+
+        // next target = average target * weighted average solve time / target time
+        let k = (n * (n + 1) / 2) as u64;
+        let next = average.mul_div(weighted, k * self.target);
+
+        target::to_compact(next.min(target::pow_limit()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Chain of `n` blocks after genesis at difficulty `d`, each `gap` seconds apart.
+    fn chain(n: usize, d: u64, gap: u64) -> Vec<Block> {
+        let mut blocks = vec![Block::genesis()];
+        for i in 1..=n {
+            let mut b = Block::new(blocks.last().unwrap().clone(), Vec::new(), None);
+            b.timestamp = 1_000 + i as u64 * gap;
+            b.bits = target::from_difficulty(d);
+            blocks.push(b);
+        }
+        blocks
+    }
+
+    /// Difficulty of the block `a` wants after `chain`.
+    fn next(a: &dyn DifficultyAlgorithm, chain: &[Block]) -> u64 {
+        target::difficulty(a.next(chain))
+    }
+
+    #[test]
+    fn retarget() {
+        let r = Retarget::new(10);
+
+        // ✅ Only retargets at the window boundary
+        assert_eq!(next(&r, &chain(8, 256, 1)), 256);
+        assert_eq!(next(&r, &chain(18, 256, 1)), 256);
+
+        assert_eq!(next(&r, &chain(19, 256, 10)), 256);
+        assert_eq!(next(&r, &chain(19, 256, 5)), 512);
+        assert_eq!(next(&r, &chain(19, 256, 20)), 128);
+        assert_eq!(next(&r, &chain(19, 256, 8)), 320);
+
+        // ✅ Clamped to a factor of four and the easiest target
+        assert_eq!(next(&r, &chain(19, 256, 0)), 1024);
+        assert_eq!(next(&r, &chain(19, 256, 1000)), 64);
+        assert_eq!(r.next(&chain(19, 1, 1000)), target::POW_LIMIT_BITS);
+    }
+
+    #[test]
+    fn lwma() {
+        let l = Lwma::new(10);
+
+        assert_eq!(l.next(&chain(0, 256, 10)), Block::genesis().bits);
+        assert_eq!(next(&l, &chain(40, 256, 10)), 256);
+        assert_eq!(next(&l, &chain(40, 256, 5)), 512);
+        assert_eq!(next(&l, &chain(40, 256, 20)), 128);
+        assert_eq!(next(&l, &chain(40, 256, 1)), 2560);
+        assert_eq!(l.next(&chain(40, 1, 60)), target::POW_LIMIT_BITS);
+    }
+}
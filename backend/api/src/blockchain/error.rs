@@ -0,0 +1,70 @@
+use std::fmt;
+
+/// Consensus rule a block can break.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rule {
+    /// The first block is not `Block::genesis()`.
+    Genesis,
+    /// `height` is not one more than the previous block's.
+    Height,
+    /// `prev` is not the previous block's hash.
+    Prev,
+    /// `timestamp` is not after the median of recent blocks, or too far ahead of our clock.
+    Timestamp,
+    /// `bits` is not the target the difficulty algorithm asks for.
+    Difficulty,
+    /// The memo or transactions are malformed.
+    Body,
+    /// `merkle_root` does not match the transactions.
+    MerkleRoot,
+    /// The hash is above the target in `bits`.
+    ProofOfWork,
+    /// A transaction spends an output that does not exist, is already spent,
+    /// or does not belong to its signer.
+    Inputs,
+    /// A transaction's signature does not match its inputs and outputs.
+    Signature,
+    /// A transaction pays out more than it spends.
+    Balance,
+    /// The first transaction is not the only coinbase, or pays more than the
+    /// mining reward plus fees.
+    Coinbase,
+    /// A transaction's lock time has not passed, or it spends an output
+    /// whose timelock has not.
+    Timelock,
+}
+
+/// Says which block of a chain failed validation and why.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InvalidBlock {
+    pub height: usize,
+    pub rule: Rule,
+}
+
+impl InvalidBlock {
+    pub fn new(height: usize, rule: Rule) -> Self {
+        InvalidBlock { height, rule }
+    }
+}
+
+impl fmt::Display for InvalidBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.rule {
+            Rule::Genesis => "does not match the genesis block",
+            Rule::Height => "has the wrong height",
+            Rule::Prev => "does not point to the previous block",
+            Rule::Timestamp => "has an invalid timestamp",
+            Rule::Difficulty => "has the wrong difficulty",
+            Rule::Body => "has a malformed body",
+            Rule::MerkleRoot => "has the wrong merkle root",
+            Rule::ProofOfWork => "does not meet its proof of work",
+            Rule::Inputs => "spends unavailable outputs",
+            Rule::Signature => "has an invalid signature",
+            Rule::Balance => "spends more than its inputs",
+            Rule::Coinbase => "has an invalid coinbase",
+            Rule::Timelock => "spends before a lock time has passed",
+        };
+
+        write!(f, "Block {} {}.", self.height, reason)
+    }
+}
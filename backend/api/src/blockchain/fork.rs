@@ -0,0 +1,98 @@
+use std::{cmp::Ordering, fmt::Debug};
+
+use crate::{
+    blockchain::{block::Block, target},
+    utils::uint::U256,
+};
+
+/// Policy deciding which of two valid chains a node should follow.
+///
+/// Both slices start right after the blocks the chains have in common, so
+/// implementations only ever need to compare the diverging branches.
+pub trait ForkChoice: Send + Debug {
+    /// Whether `candidate` should replace `current`.
+    fn prefer(&self, current: &[Block], candidate: &[Block]) -> bool;
+}
+
+/// Expected number of hashes needed to mine a block at its target.
+pub fn work(block: &Block) -> U256 {
+    target::work(block.bits)
+}
+
+/// Follows the branch with the most accumulated work.
+#[derive(Debug, Default)]
+pub struct MostWork;
+
+impl ForkChoice for MostWork {
+    fn prefer(&self, current: &[Block], candidate: &[Block]) -> bool {
+        let total = |branch: &[Block]| branch.iter().map(work).fold(U256::ZERO, |a, b| a + b);
+
+        match total(candidate).cmp(&total(current)) {
+            Ordering::Greater => true,
+            Ordering::Less => false,
+            Ordering::Equal => tiebreak(current, candidate),
+        }
+    }
+}
+
+/// Follows the branch with the most blocks, regardless of their targets.
+#[derive(Debug, Default)]
+pub struct Longest;
+
+impl ForkChoice for Longest {
+    fn prefer(&self, current: &[Block], candidate: &[Block]) -> bool {
+        match candidate.len().cmp(&current.len()) {
+            Ordering::Greater => true,
+            Ordering::Less => false,
+            Ordering::Equal => tiebreak(current, candidate),
+        }
+    }
+}
+
+/// Breaks ties towards the lower tip hash, so every node settles on the same branch.
+fn tiebreak(current: &[Block], candidate: &[Block]) -> bool {
+    match (current.last(), candidate.last()) {
+        (Some(a), Some(b)) => b.hash().0 < a.hash().0,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn branch(difficulties: &[u64]) -> Vec<Block> {
+        let mut blocks = vec![Block::genesis()];
+        for d in difficulties {
+            let mut b = Block::new(blocks.last().unwrap().clone(), Vec::new(), None);
+            b.bits = target::from_difficulty(*d);
+            blocks.push(b);
+        }
+        blocks.split_off(1)
+    }
+
+    #[test]
+    fn most_work() {
+        let long = branch(&[1, 1, 1, 1]);
+        let short = branch(&[32, 32]);
+
+        assert!(MostWork.prefer(&long, &short));
+        assert!(!MostWork.prefer(&short, &long));
+        assert!(Longest.prefer(&short, &long));
+    }
+
+    #[test]
+    fn tie() {
+        let mut a = branch(&[4]);
+        let mut b = branch(&[4]);
+        b[0].nonce = 1;
+
+        if a[0].hash().0 > b[0].hash().0 {
+            std::mem::swap(&mut a, &mut b);
+        }
+
+        assert!(MostWork.prefer(&b, &a));
+        assert!(!MostWork.prefer(&a, &b));
+        assert!(!MostWork.prefer(&a, &a));
+    }
+}
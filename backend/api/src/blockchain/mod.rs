@@ -2,11 +2,20 @@ use std::time::Instant;
 
 use chain::Chain;
 
+use crate::transaction::wallet::Wallet;
+
 pub mod block;
 pub mod chain;
+pub mod difficulty;
+pub mod error;
+pub mod fork;
+pub mod store;
+pub mod target;
+pub mod utxo;
 
 pub fn trial() {
     let mut c = Chain::new();
+    let miner = Wallet::new().public;
 
     let mut avg = 0;
 
@@ -14,7 +23,7 @@ pub fn trial() {
         let x = format!("Block {}", i);
 
         let now = Instant::now();
-        c.add(x.as_str());
+        c.add(&miner, Vec::new(), Some(x.as_str())).unwrap();
         let elapsed = now.elapsed().as_millis();
 
         avg = (avg * (i - 1) + elapsed) / i;
@@ -26,7 +35,7 @@ pub fn trial() {
 
     match c.validate() {
         Ok(_) => println!("YAY"),
-        Err(_) => println!("AWW"),
+        Err(e) => println!("AWW: {}", e),
     }
 }
 
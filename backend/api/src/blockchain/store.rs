@@ -0,0 +1,409 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use bincode::{deserialize, serialize};
+
+use crate::{blockchain::block::Block, utils::hash::Hash};
+
+const BLOCKS_FILE: &str = "blocks.dat";
+const INDEX_FILE: &str = "index.dat";
+
+/// Each record in the block file is `[len: u32][checksum: 32 bytes][payload]`.
+const RECORD_HEADER: usize = 4 + 32;
+/// Each index entry is `[offset: u64][block hash: 32 bytes]`, one per height.
+const INDEX_ENTRY: usize = 8 + 32;
+
+/// Storage backend the `Chain` writes through to.
+pub trait Store: Send + Debug {
+    /// Returns every stored block in height order.
+    fn load(&mut self) -> Result<Vec<Block>, String>;
+
+    fn append(&mut self, block: &Block) -> Result<(), String>;
+
+    /// Drops every block at or above `height`.
+    fn truncate(&mut self, height: usize) -> Result<(), String>;
+
+    /// Drops every block at or above `height` and appends `blocks` in their
+    /// place.
+    fn replace(&mut self, height: usize, blocks: &[Block]) -> Result<(), String> {
+        self.truncate(height)?;
+        blocks.iter().try_for_each(|b| self.append(b))
+    }
+
+    fn height_of(&self, hash: &Hash) -> Option<usize>;
+}
+
+/// Keeps blocks in memory only, so nothing survives a restart.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    blocks: Vec<Block>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+}
+
+impl Store for MemoryStore {
+    fn load(&mut self) -> Result<Vec<Block>, String> {
+        Ok(self.blocks.clone())
+    }
+
+    fn append(&mut self, block: &Block) -> Result<(), String> {
+        self.blocks.push(block.clone());
+        Ok(())
+    }
+
+    fn truncate(&mut self, height: usize) -> Result<(), String> {
+        self.blocks.truncate(height);
+        Ok(())
+    }
+
+    fn height_of(&self, hash: &Hash) -> Option<usize> {
+        self.blocks.iter().position(|b| &b.hash() == hash)
+    }
+}
+
+/// Append-only block file plus a fixed-width index keyed by height, with an
+/// in-memory hash -> height map rebuilt on open.
+///
+/// The block file is the source of truth. A record cut short by a crash is
+/// dropped on open, and the index is rewritten whenever it disagrees with the
+/// block file.
+#[derive(Debug)]
+pub struct FileStore {
+    blocks: File,
+    index: File,
+    offsets: Vec<u64>,
+    heights: HashMap<String, usize>,
+}
+
+impl FileStore {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, String> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+
+        let open = |name: &str| -> Result<File, String> {
+            let path: PathBuf = dir.join(name);
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)
+                .map_err(|e| e.to_string())
+        };
+
+        let mut store = FileStore {
+            blocks: open(BLOCKS_FILE)?,
+            index: open(INDEX_FILE)?,
+            offsets: Vec::new(),
+            heights: HashMap::new(),
+        };
+
+        store.recover()?;
+        Ok(store)
+    }
+
+    /// Scans the block file, cuts off any torn or corrupt trailing record and
+    /// rebuilds the index from what remains.
+    fn recover(&mut self) -> Result<(), String> {
+        let mut bytes = Vec::new();
+        self.blocks
+            .seek(SeekFrom::Start(0))
+            .map_err(|e| e.to_string())?;
+        self.blocks
+            .read_to_end(&mut bytes)
+            .map_err(|e| e.to_string())?;
+
+        let mut entries = Vec::new();
+        let mut offset = 0;
+
+        while let Some((block, next)) = decode_record(&bytes, offset) {
+            entries.push((offset as u64, block.hash()));
+            offset = next;
+        }
+
+        if offset < bytes.len() {
+            eprintln!(
+                "⚠️ Warning: dropping {} trailing bytes of the block file.",
+                bytes.len() - offset
+            );
+            self.blocks
+                .set_len(offset as u64)
+                .map_err(|e| e.to_string())?;
+            self.blocks.sync_data().map_err(|e| e.to_string())?;
+        }
+
+        let mut index = Vec::with_capacity(entries.len() * INDEX_ENTRY);
+        for (offset, hash) in entries.iter() {
+            index.extend_from_slice(&encode_entry(*offset, hash)?);
+        }
+
+        let mut stored = Vec::new();
+        self.index
+            .seek(SeekFrom::Start(0))
+            .map_err(|e| e.to_string())?;
+        self.index
+            .read_to_end(&mut stored)
+            .map_err(|e| e.to_string())?;
+
+        if stored != index {
+            self.index.set_len(0).map_err(|e| e.to_string())?;
+            self.index
+                .seek(SeekFrom::Start(0))
+                .map_err(|e| e.to_string())?;
+            self.index.write_all(&index).map_err(|e| e.to_string())?;
+            self.index.sync_data().map_err(|e| e.to_string())?;
+        }
+
+        self.offsets = entries.iter().map(|(offset, _)| *offset).collect();
+        self.heights = entries
+            .into_iter()
+            .enumerate()
+            .map(|(height, (_, hash))| (hash.0, height))
+            .collect();
+
+        Ok(())
+    }
+
+    /// Writes `record` at `offset` of the block file and indexes it.
+    fn write(&mut self, offset: u64, record: &[u8], hash: &Hash) -> Result<(), String> {
+        self.blocks.write_all(record).map_err(|e| e.to_string())?;
+        self.blocks.sync_data().map_err(|e| e.to_string())?;
+
+        self.index
+            .seek(SeekFrom::Start((self.offsets.len() * INDEX_ENTRY) as u64))
+            .map_err(|e| e.to_string())?;
+        self.index
+            .write_all(&encode_entry(offset, hash)?)
+            .map_err(|e| e.to_string())?;
+        self.index.sync_data().map_err(|e| e.to_string())
+    }
+}
+
+impl Store for FileStore {
+    fn load(&mut self) -> Result<Vec<Block>, String> {
+        let mut bytes = Vec::new();
+        self.blocks
+            .seek(SeekFrom::Start(0))
+            .map_err(|e| e.to_string())?;
+        self.blocks
+            .read_to_end(&mut bytes)
+            .map_err(|e| e.to_string())?;
+
+        self.offsets
+            .iter()
+            .map(|offset| {
+                decode_record(&bytes, *offset as usize)
+                    .map(|(block, _)| block)
+                    .ok_or("Stored block is corrupt.".to_owned())
+            })
+            .collect()
+    }
+
+    fn append(&mut self, block: &Block) -> Result<(), String> {
+        let hash = block.hash();
+        let record = encode_record(block)?;
+
+        let offset = self
+            .blocks
+            .seek(SeekFrom::End(0))
+            .map_err(|e| e.to_string())?;
+
+        // Cut off whatever part of the block got written, so the next one
+        // does not land after a record the index never learnt about.
+        if let Err(e) = self.write(offset, &record, &hash) {
+            self.blocks
+                .set_len(offset)
+                .and_then(|_| self.blocks.sync_data())
+                .map_err(|r| format!("{} {}", e, r))?;
+            self.index
+                .set_len((self.offsets.len() * INDEX_ENTRY) as u64)
+                .map_err(|r| format!("{} {}", e, r))?;
+            return Err(e);
+        }
+
+        self.heights.insert(hash.0, self.offsets.len());
+        self.offsets.push(offset);
+
+        Ok(())
+    }
+
+    fn truncate(&mut self, height: usize) -> Result<(), String> {
+        if height >= self.offsets.len() {
+            return Ok(());
+        }
+
+        // Shrink the index first, so a crash in between leaves extra block
+        // records that recovery re-indexes rather than dangling index entries.
+        self.index
+            .set_len((height * INDEX_ENTRY) as u64)
+            .map_err(|e| e.to_string())?;
+        self.index.sync_data().map_err(|e| e.to_string())?;
+
+        self.blocks
+            .set_len(self.offsets[height])
+            .map_err(|e| e.to_string())?;
+        self.blocks.sync_data().map_err(|e| e.to_string())?;
+
+        self.offsets.truncate(height);
+        self.heights.retain(|_, h| *h < height);
+
+        Ok(())
+    }
+
+    fn height_of(&self, hash: &Hash) -> Option<usize> {
+        self.heights.get(&hash.0).copied()
+    }
+}
+
+fn encode_record(block: &Block) -> Result<Vec<u8>, String> {
+    let payload = serialize(block).map_err(|e| e.to_string())?;
+    let checksum = hex::decode(Hash::new(&payload)?.0).map_err(|e| e.to_string())?;
+
+    let mut record = Vec::with_capacity(RECORD_HEADER + payload.len());
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&checksum);
+    record.extend_from_slice(&payload);
+
+    Ok(record)
+}
+
+/// Decodes the record starting at `offset`, returning the block and the offset
+/// of the next record, or `None` if the record is incomplete or corrupt.
+fn decode_record(bytes: &[u8], offset: usize) -> Option<(Block, usize)> {
+    let header = bytes.get(offset..offset + RECORD_HEADER)?;
+    let len = u32::from_le_bytes(header[..4].try_into().ok()?) as usize;
+    let payload = bytes.get(offset + RECORD_HEADER..offset + RECORD_HEADER + len)?;
+
+    if hex::decode(Hash::new(payload).ok()?.0).ok()? != header[4..] {
+        return None;
+    }
+
+    let block = deserialize(payload).ok()?;
+    Some((block, offset + RECORD_HEADER + len))
+}
+
+fn encode_entry(offset: u64, hash: &Hash) -> Result<Vec<u8>, String> {
+    let mut entry = Vec::with_capacity(INDEX_ENTRY);
+    entry.extend_from_slice(&offset.to_le_bytes());
+    entry.extend_from_slice(&hex::decode(&hash.0).map_err(|e| e.to_string())?);
+    Ok(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    fn dir() -> PathBuf {
+        temp_dir().join(format!("chain-store-{}", Uuid::new_v4()))
+    }
+
+    fn blocks() -> Vec<Block> {
+        let genesis = Block::genesis();
+        let next = Block::new(genesis.clone(), Vec::new(), Some("foo"));
+        vec![genesis, next]
+    }
+
+    #[test]
+    fn reopen() {
+        let dir = dir();
+        let blocks = blocks();
+
+        let mut s = FileStore::open(&dir).unwrap();
+        for b in blocks.iter() {
+            s.append(b).unwrap();
+        }
+        drop(s);
+
+        let mut s = FileStore::open(&dir).unwrap();
+        assert_eq!(s.load().unwrap(), blocks);
+        assert_eq!(s.height_of(&blocks[1].hash()), Some(1));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn torn_write() {
+        let dir = dir();
+        let blocks = blocks();
+
+        let mut s = FileStore::open(&dir).unwrap();
+        for b in blocks.iter() {
+            s.append(b).unwrap();
+        }
+        drop(s);
+
+        // ❌ Simulate a crash halfway through writing the last record.
+        let path = dir.join(BLOCKS_FILE);
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        let mut s = FileStore::open(&dir).unwrap();
+        assert_eq!(s.load().unwrap(), blocks[..1]);
+        assert_eq!(s.height_of(&blocks[1].hash()), None);
+
+        s.append(&blocks[1]).unwrap();
+        assert_eq!(s.load().unwrap(), blocks);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    // ✅ Test: A block that could not be indexed is cut off the block file
+    #[test]
+    fn failed_index() {
+        let dir = dir();
+        let blocks = blocks();
+
+        let mut s = FileStore::open(&dir).unwrap();
+        s.append(&blocks[0]).unwrap();
+        let len = fs::metadata(dir.join(BLOCKS_FILE)).unwrap().len();
+
+        // ❌ An index that cannot be written to
+        let index = File::open(dir.join(INDEX_FILE)).unwrap();
+        let writable = std::mem::replace(&mut s.index, index);
+        assert!(s.append(&blocks[1]).is_err());
+        assert_eq!(fs::metadata(dir.join(BLOCKS_FILE)).unwrap().len(), len);
+
+        s.index = writable;
+        s.append(&blocks[1]).unwrap();
+        drop(s);
+
+        let mut s = FileStore::open(&dir).unwrap();
+        assert_eq!(s.load().unwrap(), blocks);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn truncate() {
+        let dir = dir();
+        let blocks = blocks();
+
+        let mut s = FileStore::open(&dir).unwrap();
+        for b in blocks.iter() {
+            s.append(b).unwrap();
+        }
+        s.truncate(1).unwrap();
+        drop(s);
+
+        let mut s = FileStore::open(&dir).unwrap();
+        assert_eq!(s.load().unwrap(), blocks[..1]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
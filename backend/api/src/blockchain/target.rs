@@ -0,0 +1,130 @@
+use crate::utils::uint::U256;
+
+/// Easiest target any block may have, as compact bits. A hash meets it with
+/// a probability of about one half.
+pub const POW_LIMIT_BITS: u32 = 0x207fffff;
+/// Target of the genesis block, about 5 leading zero bits.
+pub const GENESIS_BITS: u32 = 0x2007ffff;
+
+/// Easiest target any block may have.
+pub fn pow_limit() -> U256 {
+    from_compact(POW_LIMIT_BITS)
+}
+
+/// Expands compact bits into a 256-bit target.
+///
+/// The top byte is the length of the target in bytes and the low three bytes
+/// are its most significant digits, as in Bitcoin's `nBits`. The sign bit
+/// (`0x00800000`) is never set by `to_compact`, so targets using it are
+/// treated as zero, which no hash meets.
+pub fn from_compact(bits: u32) -> U256 {
+    let size = bits >> 24;
+    let mantissa = bits & 0x007fffff;
+
+    if bits & 0x00800000 != 0 {
+        return U256::ZERO;
+    }
+
+    if size <= 3 {
+        U256::from_u64(mantissa as u64 >> (8 * (3 - size)))
+    } else {
+        let target = U256::from_u64(mantissa as u64);
+        // Anything shifted past 256 bits would not round-trip.
+        if target.bits() + 8 * (size - 3) > 256 {
+            return U256::ZERO;
+        }
+        target << (8 * (size - 3))
+    }
+}
+
+/// Compresses a target into compact bits, dropping all but its three most
+/// significant bytes.
+pub fn to_compact(target: U256) -> u32 {
+    let mut size = target.bits().div_ceil(8);
+    let mut mantissa = if size <= 3 {
+        (target.saturating_u64() << (8 * (3 - size))) as u32
+    } else {
+        (target >> (8 * (size - 3))).saturating_u64() as u32
+    };
+
+    // Keep the sign bit clear by moving a byte into the exponent.
+    if mantissa & 0x00800000 != 0 {
+        mantissa >>= 8;
+        size += 1;
+    }
+
+    mantissa | size << 24
+}
+
+/// Whether a hash digest, read as a big-endian number, meets `bits`.
+pub fn meets(digest: [u8; 32], bits: u32) -> bool {
+    U256::from_be_bytes(digest) <= from_compact(bits)
+}
+
+/// Expected number of hashes needed to meet `bits`, i.e. `2^256 / (target + 1)`.
+pub fn work(bits: u32) -> U256 {
+    let target = from_compact(bits);
+    // 2^256 does not fit, but (2^256 - target - 1) / (target + 1) + 1 does.
+    (!target / (target + U256::ONE)) + U256::ONE
+}
+
+/// How many times harder `bits` is than the easiest target.
+pub fn difficulty(bits: u32) -> u64 {
+    (pow_limit() / from_compact(bits)).saturating_u64()
+}
+
+/// Compact bits for a target `difficulty` times harder than the easiest one.
+pub fn from_difficulty(difficulty: u64) -> u32 {
+    to_compact(pow_limit().mul_div(1, difficulty.max(1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ✅ Test: Known compact encodings
+    #[test]
+    fn compact() {
+        assert_eq!(from_compact(0x01003456), U256::ZERO);
+        assert_eq!(from_compact(0x01123456), U256::from_u64(0x12));
+        assert_eq!(from_compact(0x04123456), U256::from_u64(0x12345600));
+        assert_eq!(from_compact(0x05009234), U256::from_u64(0x92340000));
+        assert_eq!(from_compact(0x04923456), U256::ZERO);
+        assert_eq!(from_compact(0xff123456), U256::ZERO);
+        assert_eq!(
+            from_compact(POW_LIMIT_BITS),
+            (U256::ONE << 255) - (U256::ONE << 232)
+        );
+
+        assert_eq!(to_compact(U256::from_u64(0x12)), 0x01120000);
+        assert_eq!(to_compact(U256::from_u64(0x92340000)), 0x05009234);
+        assert_eq!(to_compact(U256::from_u64(0x123456789a)), 0x05123456);
+        for bits in [GENESIS_BITS, POW_LIMIT_BITS, 0x1d00ffff, 0x03123456] {
+            assert_eq!(to_compact(from_compact(bits)), bits);
+        }
+    }
+
+    #[test]
+    fn meets_target() {
+        assert!(!meets([0xff; 32], GENESIS_BITS));
+
+        // ✅ A hash equal to the target meets it
+        let mut digest = [0; 32];
+        digest[..3].copy_from_slice(&[0x07, 0xff, 0xff]);
+        assert!(meets(digest, GENESIS_BITS));
+
+        digest[3] = 0x01;
+        assert!(!meets(digest, GENESIS_BITS));
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(difficulty(POW_LIMIT_BITS), 1);
+        assert_eq!(difficulty(GENESIS_BITS), 16);
+        assert_eq!(difficulty(from_difficulty(1_000)), 1_000);
+
+        assert_eq!(work(POW_LIMIT_BITS), U256::from_u64(2));
+        assert_eq!(work(GENESIS_BITS), U256::from_u64(32));
+        assert!(work(from_difficulty(64)) > work(GENESIS_BITS));
+    }
+}
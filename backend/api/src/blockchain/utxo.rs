@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+
+use crate::{
+    blockchain::{
+        block::Block,
+        error::{InvalidBlock, Rule},
+    },
+    transaction::{
+        amount::Amount,
+        input::OutPoint,
+        output::{Lock, Output},
+        transaction::{mining_reward, Transaction},
+    },
+};
+
+/// An unspent output and the height and timestamp of the block that created it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coin {
+    pub output: Output,
+    pub height: usize,
+    pub time: u64,
+}
+
+/// Coins a block spent, so they can be restored if it is disconnected.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Undo {
+    spent: Vec<(OutPoint, Coin)>,
+    /// Height of the set before the block was connected.
+    height: usize,
+}
+
+/// Every output in the chain that has not been spent yet.
+#[derive(Debug, Clone)]
+pub struct UtxoSet {
+    coins: HashMap<OutPoint, Coin>,
+    /// Height of the last connected block.
+    height: usize,
+    /// Network the transactions spending the coins are signed for.
+    chain_id: u32,
+}
+
+impl UtxoSet {
+    pub fn new(chain_id: u32) -> Self {
+        UtxoSet {
+            coins: HashMap::new(),
+            height: 0,
+            chain_id,
+        }
+    }
+
+    pub fn get(&self, outpoint: &OutPoint) -> Option<&Coin> {
+        self.coins.get(outpoint)
+    }
+
+    /// Height of the last connected block, so the next block is one above.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn chain_id(&self) -> u32 {
+        self.chain_id
+    }
+
+    /// Sum of every unspent output, i.e. the coins in circulation.
+    pub fn total(&self) -> Amount {
+        Amount::sum(self.coins.values().map(|c| c.output.amount)).unwrap_or(Amount::MAX)
+    }
+
+    /// Unspent outputs locked by `lock`, e.g. paying to a public key.
+    pub fn owned_by(&self, lock: impl Into<Lock>) -> Vec<(OutPoint, Output)> {
+        let lock = lock.into();
+        self.coins
+            .iter()
+            .filter(|(_, c)| c.output.lock == lock)
+            .map(|(p, c)| (p.clone(), c.output.clone()))
+            .collect()
+    }
+
+    /// Unspent outputs locked by `lock` whose timelocks let a block at
+    /// `height` with timestamp `time` spend them.
+    pub fn spendable_by(
+        &self,
+        lock: impl Into<Lock>,
+        height: usize,
+        time: u64,
+    ) -> Vec<(OutPoint, Output)> {
+        let lock = lock.into();
+        self.coins
+            .iter()
+            .filter(|(_, c)| c.output.lock == lock)
+            .filter(|(_, c)| c.output.timelock.is_open(c.height, c.time, height, time))
+            .map(|(p, c)| (p.clone(), c.output.clone()))
+            .collect()
+    }
+
+    /// Fee `t` pays, i.e. its input total less its output total, or `None`
+    /// if an input is not an unspent coin or the outputs exceed the inputs.
+    pub fn fee(&self, t: &Transaction) -> Option<Amount> {
+        if t.is_coinbase() {
+            return None;
+        }
+
+        let inputs = t
+            .inputs
+            .iter()
+            .map(|i| self.coins.get(&i.outpoint).map(|c| c.output.amount))
+            .collect::<Option<Vec<Amount>>>()?;
+
+        Amount::sum(inputs)?.checked_sub(Amount::sum(t.outputs.iter().map(|o| o.amount))?)
+    }
+
+    /// Spends the inputs and adds the outputs of every transaction in
+    /// `block`, in order.
+    ///
+    /// The first transaction must be the only coinbase, must name the height
+    /// of `block` and may pay out at most the mining reward for that height
+    /// plus the fees of the others. Every other transaction must be signed,
+    /// past its lock time, may only spend coins that exist, are unspent,
+    /// match its locks and whose timelocks have passed, and may not pay out
+    /// more than it spends. Nothing changes if any of this fails.
+    pub fn connect(&mut self, block: &Block) -> Result<Undo, InvalidBlock> {
+        let mut undo = Undo {
+            spent: Vec::new(),
+            height: self.height,
+        };
+
+        match self.apply(block, &mut undo) {
+            Ok(()) => {
+                self.height = block.height;
+                Ok(undo)
+            }
+            Err((applied, rule)) => {
+                self.disconnect(&block.transactions[..applied], undo);
+                Err(InvalidBlock::new(block.height, rule))
+            }
+        }
+    }
+
+    /// Reverses `connect`, given the transactions it connected and the undo
+    /// data it returned.
+    pub fn disconnect(&mut self, transactions: &[Transaction], undo: Undo) {
+        // Restore first, so coins created and spent within the block end up removed.
+        self.coins.extend(undo.spent);
+        self.height = undo.height;
+
+        for t in transactions.iter() {
+            for (p, _) in t.outpoints() {
+                self.coins.remove(&p);
+            }
+        }
+    }
+
+    /// Does the work of `connect`, failing with the number of transactions
+    /// whose outputs were already added and the broken rule.
+    fn apply(&mut self, block: &Block, undo: &mut Undo) -> Result<(), (usize, Rule)> {
+        let mut fees = Amount::ZERO;
+
+        for (i, t) in block.transactions.iter().enumerate() {
+            match (i, t.is_coinbase()) {
+                (0, true) if t.height == Some(block.height) => {}
+                (_, false) if t.height.is_some() => return Err((i, Rule::Coinbase)),
+                (0, _) | (_, true) => return Err((i, Rule::Coinbase)),
+                (_, false) if !self.unlocked(t, block.height, block.timestamp) => {
+                    return Err((i, Rule::Timelock))
+                }
+                (_, false) => {
+                    let fee = self.spend(t, undo).map_err(|rule| (i, rule))?;
+                    fees = fees.checked_add(fee).ok_or((i, Rule::Balance))?;
+                }
+            }
+
+            for (p, output) in t.outpoints() {
+                self.coins.insert(
+                    p,
+                    Coin {
+                        output: output.clone(),
+                        height: block.height,
+                        time: block.timestamp,
+                    },
+                );
+            }
+        }
+
+        let applied = block.transactions.len();
+        let coinbase = block.transactions.first().ok_or((0, Rule::Coinbase))?;
+        let paid = Amount::sum(coinbase.outputs.iter().map(|o| o.amount));
+
+        match (paid, mining_reward(block.height).checked_add(fees)) {
+            (Some(paid), Some(allowed)) if paid <= allowed => Ok(()),
+            _ => Err((applied, Rule::Coinbase)),
+        }
+    }
+
+    /// Whether every input of `t` spends an unspent coin with its lock.
+    pub fn owned(&self, t: &Transaction) -> bool {
+        t.inputs.iter().all(|i| {
+            self.coins
+                .get(&i.outpoint)
+                .is_some_and(|c| c.output.lock == i.lock)
+        })
+    }
+
+    /// Whether `t` may go into a block at `height` with timestamp `time`: its
+    /// lock time has passed, as have the timelocks of the coins it spends
+    /// that exist.
+    pub fn unlocked(&self, t: &Transaction, height: usize, time: u64) -> bool {
+        t.is_final(height, time)
+            && t.inputs.iter().all(|i| {
+                self.coins.get(&i.outpoint).is_none_or(|c| {
+                    c.output.timelock.is_open(c.height, c.time, height, time)
+                        && i.timelock.is_open(c.height, c.time, height, time)
+                })
+            })
+    }
+
+    /// Removes the coins `t` spends, recording them in `undo`, and returns its fee.
+    fn spend(&mut self, t: &Transaction, undo: &mut Undo) -> Result<Amount, Rule> {
+        if t.is_coinbase() || !self.owned(t) {
+            return Err(Rule::Inputs);
+        }
+        if t.verify(self.chain_id).is_err() {
+            return Err(Rule::Signature);
+        }
+
+        let fee = self.fee(t).ok_or(Rule::Balance)?;
+
+        for i in t.inputs.iter() {
+            // A second spend of the same outpoint finds nothing left to remove.
+            let coin = self.coins.remove(&i.outpoint).ok_or(Rule::Inputs)?;
+            undo.spent.push((i.outpoint.clone(), coin));
+        }
+
+        Ok(fee)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{
+        amount::Amount,
+        input::Input,
+        output::Timelock,
+        transaction::{DEFAULT_CHAIN_ID, INITIAL_REWARD},
+        wallet::Wallet,
+    };
+
+    /// UTXO set holding one reward paid to `w`, and the block that paid it.
+    fn funded(w: &Wallet) -> (UtxoSet, Block) {
+        let mut utxos = UtxoSet::new(DEFAULT_CHAIN_ID);
+        let block = Block::new(
+            Block::genesis(),
+            vec![Transaction::reward(&w.public, INITIAL_REWARD, 1)],
+            None,
+        );
+        utxos.connect(&block).unwrap();
+        (utxos, block)
+    }
+
+    fn spend(utxos: &UtxoSet, from: &Wallet, to: &Wallet, prev: &Block) -> Block {
+        let coins = utxos.owned_by(&from.public);
+        let t = Transaction::new(
+            from,
+            &coins,
+            &to.public,
+            Amount::from_coins(10),
+            Amount::ZERO,
+            DEFAULT_CHAIN_ID,
+        )
+        .unwrap();
+        Block::new(prev.clone(), vec![coinbase(prev.height + 1), t], None)
+    }
+
+    fn coinbase(height: usize) -> Transaction {
+        Transaction::reward(&Wallet::new().public, INITIAL_REWARD, height)
+    }
+
+    #[test]
+    fn connect() {
+        let (w, w2) = (Wallet::new(), Wallet::new());
+        let (mut utxos, funding) = funded(&w);
+
+        let block = spend(&utxos, &w, &w2, &funding);
+        utxos.connect(&block).unwrap();
+
+        assert_eq!(
+            utxos.owned_by(&w.public)[0].1.amount,
+            Amount::from_coins(40)
+        );
+        assert_eq!(
+            utxos.owned_by(&w2.public)[0].1.amount,
+            Amount::from_coins(10)
+        );
+
+        // ✅ The same coins cannot be spent twice
+        let twice = Block::new(
+            block.clone(),
+            vec![coinbase(3), block.transactions[1].clone()],
+            None,
+        );
+        assert_eq!(
+            utxos.connect(&twice),
+            Err(InvalidBlock::new(3, Rule::Inputs))
+        );
+        assert_eq!(utxos.owned_by(&w2.public).len(), 1);
+    }
+
+    // ✅ Test: Only the owner of a coin can spend it
+    #[test]
+    fn foreign_coins() {
+        let (w, w2) = (Wallet::new(), Wallet::new());
+        let (mut utxos, funding) = funded(&w);
+
+        let mut block = spend(&utxos, &w, &w2, &funding);
+        let t = &mut block.transactions[1];
+        t.inputs[0] = Input::new(t.inputs[0].outpoint.clone(), Lock::Key(w2.public));
+        t.sign(&w2, DEFAULT_CHAIN_ID).unwrap();
+
+        assert_eq!(
+            utxos.connect(&block),
+            Err(InvalidBlock::new(2, Rule::Inputs))
+        );
+        assert_eq!(utxos.owned_by(&w.public).len(), 1);
+    }
+
+    // ✅ Test: Coins of several owners can be spent together once all have signed
+    #[test]
+    fn joint() {
+        let (w, w2) = (Wallet::new(), Wallet::new());
+        let (mut utxos, funding) = funded(&w);
+        let block = Block::new(
+            funding,
+            vec![Transaction::reward(&w2.public, INITIAL_REWARD, 2)],
+            None,
+        );
+        utxos.connect(&block).unwrap();
+
+        let receiver = Wallet::new().public;
+        let coins = [utxos.owned_by(&w.public), utxos.owned_by(&w2.public)].concat();
+        let mut t = Transaction::build(
+            &coins,
+            vec![Output::new(&receiver, Amount::from_coins(100))],
+        );
+        t.sign(&w, DEFAULT_CHAIN_ID).unwrap();
+
+        let spend = |t: &Transaction| Block::new(block.clone(), vec![coinbase(3), t.clone()], None);
+        assert_eq!(
+            utxos.connect(&spend(&t)),
+            Err(InvalidBlock::new(3, Rule::Signature))
+        );
+
+        t.sign(&w2, DEFAULT_CHAIN_ID).unwrap();
+        utxos.connect(&spend(&t)).unwrap();
+        assert_eq!(
+            utxos.owned_by(&receiver)[0].1.amount,
+            Amount::from_coins(100)
+        );
+    }
+
+    // ✅ Test: Coins wait for their timelock, and spends for their lock time
+    #[test]
+    fn timelock() {
+        let (w, w2) = (Wallet::new(), Wallet::new());
+        let (mut utxos, funding) = funded(&w);
+
+        let coins = utxos.owned_by(&w.public);
+        let vesting =
+            Output::new(&w2.public, Amount::from_coins(10)).with_timelock(Timelock::Blocks(2));
+        let mut t = Transaction::pay(&coins, vesting, Amount::ZERO, &w.public).unwrap();
+        t.sign(&w, DEFAULT_CHAIN_ID).unwrap();
+        let block = Block::new(funding, vec![coinbase(2), t], None);
+        utxos.connect(&block).unwrap();
+
+        // ❌ One block after the payment
+        let early = spend(&utxos, &w2, &w, &block);
+        assert_eq!(
+            utxos.connect(&early),
+            Err(InvalidBlock::new(3, Rule::Timelock))
+        );
+
+        let empty = Block::new(block, vec![coinbase(3)], None);
+        utxos.connect(&empty).unwrap();
+
+        // ❌ Two blocks after, but the spend itself waits for a later one
+        let mut late = spend(&utxos, &w2, &w, &empty);
+        late.transactions[1].lock_time = 5;
+        late.transactions[1].sign(&w2, DEFAULT_CHAIN_ID).unwrap();
+        assert_eq!(
+            utxos.connect(&late),
+            Err(InvalidBlock::new(4, Rule::Timelock))
+        );
+
+        utxos.connect(&spend(&utxos, &w2, &w, &empty)).unwrap();
+        assert_eq!(utxos.owned_by(&w.public).len(), 2);
+    }
+
+    // ✅ Test: The coinbase may claim the fees of its block but no more
+    #[test]
+    fn fees() {
+        let (w, w2) = (Wallet::new(), Wallet::new());
+        let (mut utxos, funding) = funded(&w);
+
+        let mut t = spend(&utxos, &w, &w2, &funding).transactions[1].clone();
+        t.outputs[0].amount = Amount::from_coins(39);
+        t.sign(&w, DEFAULT_CHAIN_ID).unwrap();
+        assert_eq!(utxos.fee(&t), Some(Amount::from_coins(1)));
+
+        let block = |paid| {
+            let coinbase = Transaction::reward(&w2.public, paid, 2);
+            Block::new(funding.clone(), vec![coinbase, t.clone()], None)
+        };
+
+        assert_eq!(
+            utxos.connect(&block(Amount::from_coins(52))),
+            Err(InvalidBlock::new(2, Rule::Coinbase))
+        );
+        assert_eq!(utxos.owned_by(&w.public).len(), 1);
+        assert!(utxos.connect(&block(Amount::from_coins(51))).is_ok());
+    }
+
+    #[test]
+    fn disconnect() {
+        let (w, w2) = (Wallet::new(), Wallet::new());
+        let (mut utxos, funding) = funded(&w);
+        let before = utxos.owned_by(&w.public);
+
+        let block = spend(&utxos, &w, &w2, &funding);
+        let undo = utxos.connect(&block).unwrap();
+        utxos.disconnect(&block.transactions, undo);
+
+        assert_eq!(utxos.owned_by(&w.public), before);
+        assert!(utxos.owned_by(&w2.public).is_empty());
+    }
+}
@@ -1,65 +1,129 @@
-use std::{collections::HashMap, env, time::Instant};
-
-use blockchain::{block::Block, chain::Chain, trial};
+use std::env;
+
+use blockchain::{
+    block::{Block, Header, InclusionProof},
+    chain::Chain,
+    difficulty::{DifficultyAlgorithm, Lwma, Retarget, TARGET_BLOCK_TIME},
+    fork::{ForkChoice, Longest, MostWork},
+    store::FileStore,
+    target, trial,
+};
 
 mod blockchain;
 //mod server;
-//mod miner;
+mod miner;
 mod transaction;
 mod utils;
 
 use axum::{
-    extract::{
-        ws::{Message, WebSocket},
-        State, WebSocketUpgrade,
-    },
-    response::{IntoResponse, Redirect},
+    extract::{Path, Query, State},
     routing::{get, post},
     Json, Router,
 };
-use futures_util::{
-    sink::SinkExt,
-    stream::{SplitSink, SplitStream, StreamExt},
-};
-use hex::decode;
+use miner::Miner;
 use reqwest::Client;
 use secp256k1::PublicKey;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc;
-use transaction::{pool::Pool, transaction::Transaction, wallet::Wallet};
-use uuid::Uuid;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::available_parallelism;
+use transaction::{
+    amount::Amount,
+    htlc::Htlc,
+    output::{Lock, Output, Timelock},
+    pool::{Pool, MAX_BLOCK_SIZE},
+    transaction::{mining_reward, next_halving, Transaction, DEFAULT_CHAIN_ID},
+    wallet::Wallet,
+};
+use utils::hash::Hash;
 
 #[derive(Clone)]
 struct AppState {
     c: Arc<Mutex<Chain>>,
     p: Arc<Mutex<Pool>>,
     w: Arc<Mutex<Wallet>>,
+    m: Arc<Miner>,
 }
 
 #[tokio::main]
 async fn main() {
-    //x();
-    let c = Arc::new(Mutex::new(Chain::new()));
-    let p = Arc::new(Mutex::new(Pool::new()));
-    let w = Arc::new(Mutex::new(Wallet::new()));
-
-    let s = AppState { c, p, w };
+    if env::args().nth(1).as_deref() == Some("trial") {
+        trial();
+        return;
+    }
 
     let port: u16 = env::var("API_PORT")
         .unwrap_or_else(|_| "3001".to_string()) // Default to 4000
         .parse()
         .expect("Invalid PORT number");
 
+    // Each node on this machine keeps its own ledger.
+    let dir = env::var("DATA_DIR").unwrap_or_else(|_| format!("data/{}", port));
+    let store = FileStore::open(&dir).expect("Failed to open block store");
+
+    let fork: Box<dyn ForkChoice> = match env::var("FORK_CHOICE").as_deref() {
+        Ok("longest") => Box::new(Longest),
+        _ => Box::new(MostWork),
+    };
+
+    let block_time: u64 = env::var("BLOCK_TIME")
+        .ok()
+        .map(|t| t.parse().expect("Invalid BLOCK_TIME number"))
+        .inspect(|t| assert!(*t > 0, "BLOCK_TIME must be at least one second"))
+        .unwrap_or(TARGET_BLOCK_TIME);
+
+    // Nodes of separate networks, e.g. dev and staging, must not accept each
+    // other's transactions.
+    let chain_id: u32 = env::var("CHAIN_ID")
+        .ok()
+        .map(|id| id.parse().expect("Invalid CHAIN_ID number"))
+        .unwrap_or(DEFAULT_CHAIN_ID);
+
+    let difficulty: Box<dyn DifficultyAlgorithm> = match env::var("DIFFICULTY").as_deref() {
+        Ok("lwma") => Box::new(Lwma::new(block_time)),
+        _ => Box::new(Retarget::new(block_time)),
+    };
+
+    let c = Arc::new(Mutex::new(
+        Chain::open(Box::new(store), difficulty, chain_id)
+            .expect("Failed to load blockchain")
+            .with_fork_choice(fork),
+    ));
+    let p = Arc::new(Mutex::new(Pool::new()));
+    let w = Arc::new(Mutex::new(
+        Wallet::open(&dir).expect("Failed to load wallet"),
+    ));
+
+    let threads: usize = env::var("MINER_THREADS")
+        .ok()
+        .map(|t| t.parse().expect("Invalid MINER_THREADS number"))
+        .unwrap_or_else(|| available_parallelism().map_or(1, |n| n.get()));
+    let m = Arc::new(Miner::new(threads));
+
+    let s = AppState { c, p, w, m };
+
     let router = Router::new()
         .route("/api/chain/get", get(get_chain))
+        .route("/api/chain/validate", get(validate_chain))
+        .route("/api/chain/headers", get(get_headers))
+        .route("/api/chain/block/{hash}", get(get_block))
+        .route("/api/chain/difficulty", get(get_difficulty))
+        .route("/api/chain/supply", get(get_supply))
+        .route("/api/chain/proof/{txid}", get(get_proof))
+        .route("/api/chain/proof/verify", post(verify_proof))
         .route("/api/chain/mine", post(mine_block))
         .route("/api/chain/replace", post(replace_chain))
+        .route("/api/chain/branch", post(connect_branch))
         .route("/api/transaction/get", get(get_pool))
         .route("/api/transaction/create", post(create_transaction))
         .route("/api/transaction/update", post(update_transaction))
+        .route("/api/transaction/sign", post(sign_transaction))
+        .route("/api/transaction/propose", post(propose_transaction))
+        .route("/api/transaction/combine", post(combine_transactions))
+        .route("/api/htlc/create", post(create_htlc))
+        .route("/api/htlc/claim", post(claim_htlc))
+        .route("/api/htlc/refund", post(refund_htlc))
         .route("/api/public_key", get(get_public_key))
         .route("/api/mine", get(mine))
         .with_state(s);
@@ -72,26 +136,104 @@ async fn main() {
     axum::serve(listener, router).await.unwrap();
 }
 
+/// Blocks are checked as they are added, so this only serves them.
 async fn get_chain(State(s): State<AppState>) -> Json<Value> {
+    let c = s.c.lock().unwrap();
+    Json(json!(&c.chain))
+}
+
+/// Diagnostic replay of the whole chain, which holds the chain lock while
+/// it checks every block again.
+async fn validate_chain(State(s): State<AppState>) -> Json<Value> {
     let c = s.c.lock().unwrap();
     match c.validate() {
-        Ok(_) => {
-            let chain = json!(&c.chain);
-            Json(chain)
+        Ok(()) => Json(json!({ "valid": true })),
+        Err(e) => Json(json!({ "valid": false, "error": e.to_string() })),
+    }
+}
+
+async fn get_headers(State(s): State<AppState>) -> Json<Value> {
+    let c = s.c.lock().unwrap();
+    let headers: Vec<Header> = c.chain.iter().map(|b| b.header()).collect();
+    Json(json!(headers))
+}
+
+#[derive(Debug, Deserialize)]
+struct DifficultyQuery {
+    /// Convert this difficulty to bits instead of reporting the next block's.
+    difficulty: Option<u64>,
+}
+
+async fn get_difficulty(
+    State(s): State<AppState>,
+    Query(q): Query<DifficultyQuery>,
+) -> Json<Value> {
+    let bits = match q.difficulty {
+        Some(d) => target::from_difficulty(d),
+        None => s.c.lock().unwrap().next_bits(),
+    };
+    Json(json!({ "bits": bits, "difficulty": target::difficulty(bits) }))
+}
+
+async fn get_supply(State(s): State<AppState>) -> Json<Value> {
+    let c = s.c.lock().unwrap();
+    let height = c.chain.len() - 1;
+
+    Json(json!({
+        "height": height,
+        "subsidy": mining_reward(height + 1),
+        "circulating": c.utxos().total(),
+        "next_halving": next_halving(height),
+    }))
+}
+
+async fn get_block(State(s): State<AppState>, Path(hash): Path<String>) -> Json<Value> {
+    let c = s.c.lock().unwrap();
+    match c.height_of(&Hash(hash)) {
+        Some(height) => Json(json!(&c.chain[height])),
+        None => Json(json!("{}")),
+    }
+}
+
+async fn get_proof(State(s): State<AppState>, Path(txid): Path<Hash>) -> Json<Value> {
+    let c = s.c.lock().unwrap();
+
+    for block in c.chain.iter() {
+        if let Some(proof) = block.prove(&txid) {
+            return Json(json!(ProofData {
+                block: block.hash(),
+                proof,
+                tx: txid,
+            }));
         }
-        Err(_) => Json(json!("{}")),
     }
+
+    Json(json!("{}"))
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct ProofData {
+    tx: Hash,
+    block: Hash,
+    proof: InclusionProof,
+}
+
+async fn verify_proof(Json(data): Json<ProofData>) -> Json<Value> {
+    Json(json!(data.proof.verify(&data.tx, &data.block)))
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct BlockData {
-    data: String,
+    memo: String,
 }
 
 async fn mine_block(State(s): State<AppState>, Json(data): Json<BlockData>) {
-    let mut c = s.c.lock().unwrap();
-    c.add(data.data.as_str());
+    if let Err(e) = mine_next(&s, Some(data.memo), false).await {
+        eprintln!("❌ Failed to add block: {}", e);
+        return;
+    }
 
+    let c = lock(&s.c, "Blockchain");
     tokio::spawn(notify_p2p_server(c.chain.clone()));
 
     //Redirect::permanent("/api/chain")
@@ -101,19 +243,42 @@ async fn replace_chain(State(s): State<AppState>, Json(chain): Json<Vec<Block>>)
     let mut c = s.c.lock().unwrap();
 
     match c.replace(chain) {
-        Some(ch) => {
+        Ok(r) => {
+            s.m.interrupt();
+            lock(&s.p, "Transaction Pool").reorg(&r, c.utxos());
+            println!(
+                "🔀 Switched chain: {} blocks disconnected, {} connected.",
+                r.disconnected.len(),
+                r.connected.len()
+            );
             //tokio::spawn(notify_p2p_server(ch));
         }
-        None => (),
-    };
+        Err(e) => println!("new chain rejected: {}", e),
+    }
 
     //Redirect::permanent("/api/chain")
 }
 
+async fn connect_branch(State(s): State<AppState>, Json(branch): Json<Vec<Block>>) -> Json<Value> {
+    let mut c = s.c.lock().unwrap();
+
+    match c.reorg(branch) {
+        Ok(r) => {
+            s.m.interrupt();
+            lock(&s.p, "Transaction Pool").reorg(&r, c.utxos());
+            Json(json!(r))
+        }
+        Err(e) => {
+            println!("branch rejected: {}", e);
+            Json(json!("{}"))
+        }
+    }
+}
+
 async fn get_pool(State(s): State<AppState>) -> Json<Value> {
     let p = s.p.lock().unwrap();
 
-    let chain = json!(&p.transactions);
+    let chain = json!(p.transactions());
 
     Json(chain)
 }
@@ -121,8 +286,31 @@ async fn get_pool(State(s): State<AppState>) -> Json<Value> {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 
 struct TransactionData {
-    receiver: PublicKey,
-    amount: f64,
+    receiver: Receiver,
+    amount: Amount,
+    /// Paid to the miner of the block that includes the transaction.
+    #[serde(default)]
+    fee: Amount,
+    /// When the receiver may spend the payment, e.g. `{"Height": 500}`.
+    #[serde(default)]
+    timelock: Timelock,
+}
+
+/// A plain public key, or a lock such as `{"Multisig": {"threshold": 2, "keys": [...]}}`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+enum Receiver {
+    Key(PublicKey),
+    Lock(Lock),
+}
+
+impl From<Receiver> for Lock {
+    fn from(r: Receiver) -> Self {
+        match r {
+            Receiver::Key(key) => Lock::Key(key),
+            Receiver::Lock(lock) => lock,
+        }
+    }
 }
 
 async fn create_transaction(State(s): State<AppState>, Json(data): Json<TransactionData>) {
@@ -130,65 +318,262 @@ async fn create_transaction(State(s): State<AppState>, Json(data): Json<Transact
     let mut p = s.p.lock().unwrap();
     let mut w = s.w.lock().unwrap();
 
-    let t = w.send(&data.receiver, data.amount, &c, &mut p).unwrap();
-    tokio::spawn(notify_p2p_transaction(t));
+    let payment = Output::new(data.receiver, data.amount).with_timelock(data.timelock);
+
+    match w.send_output(payment, data.fee, &c, &mut p) {
+        Ok(t) => {
+            println!("💸 Created transaction {}", t.txid().0);
+            tokio::spawn(notify_p2p_transaction(t));
+        }
+        Err(e) => eprintln!("❌ Could not create transaction: {}", e),
+    }
 }
 
 async fn update_transaction(State(s): State<AppState>, Json(transaction): Json<Transaction>) {
+    let c = s.c.lock().unwrap();
     let mut p = s.p.lock().unwrap();
 
-    p.update(transaction);
+    if let Err(e) = p.update(transaction, c.utxos()) {
+        eprintln!("❌ Transaction rejected: {}", e);
+    }
 
     //Redirect::permanent("/api/chain")
 }
 
-async fn get_public_key(State(s): State<AppState>) -> Json<Value> {
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct ProposalData {
+    /// Lock of the shared coins to spend, which must list this wallet's key.
+    lock: Lock,
+    receiver: Receiver,
+    amount: Amount,
+    #[serde(default)]
+    fee: Amount,
+}
+
+/// Starts spending coins under a multisignature lock, returning the
+/// transaction with only this wallet's signature for the other keys to sign.
+async fn propose_transaction(
+    State(s): State<AppState>,
+    Json(data): Json<ProposalData>,
+) -> Json<Value> {
+    let c = s.c.lock().unwrap();
     let w = s.w.lock().unwrap();
 
-    Json(json!(w.public))
+    match w.propose(&data.lock, data.receiver, data.amount, data.fee, &c) {
+        Ok(t) => Json(json!(t)),
+        Err(e) => {
+            eprintln!("❌ Could not propose transaction: {}", e);
+            Json(json!("{}"))
+        }
+    }
 }
 
-async fn mine(State(s): State<AppState>) -> Json<Value> {
-    let mut c = match s.c.lock() {
-        Ok(guard) => guard,
-        Err(poisoned) => {
-            eprintln!("⚠️ Warning: Blockchain mutex was poisoned! Recovering...");
-            poisoned.into_inner() // Recover from the poisoned state
-        }
+/// Merges the signatures of copies of one transaction signed by different keys.
+async fn combine_transactions(
+    State(s): State<AppState>,
+    Json(transactions): Json<Vec<Transaction>>,
+) -> Json<Value> {
+    let chain_id = s.c.lock().unwrap().chain_id();
+    let mut iter = transactions.into_iter();
+    let Some(mut combined) = iter.next() else {
+        return Json(json!("{}"));
     };
 
-    let mut p = match s.p.lock() {
-        Ok(guard) => guard,
-        Err(poisoned) => {
-            eprintln!("⚠️ Warning: Transaction Pool mutex was poisoned! Recovering...");
-            poisoned.into_inner()
+    for t in iter {
+        if let Err(e) = combined.combine(&t, chain_id) {
+            eprintln!("❌ Could not combine transactions: {}", e);
+            return Json(json!("{}"));
         }
+    }
+
+    Json(json!(combined))
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct HtlcData {
+    receiver: PublicKey,
+    /// `Hash::new` of the secret the receiver must reveal.
+    hash: Hash,
+    /// Block height, or Unix time, from which this wallet may refund.
+    deadline: u64,
+    amount: Amount,
+    #[serde(default)]
+    fee: Amount,
+}
+
+/// Locks coins of this wallet in an HTLC, returning the contract for the
+/// receiver to claim or this wallet to refund.
+async fn create_htlc(State(s): State<AppState>, Json(data): Json<HtlcData>) -> Json<Value> {
+    let c = s.c.lock().unwrap();
+    let mut p = s.p.lock().unwrap();
+    let mut w = s.w.lock().unwrap();
+
+    let htlc = Htlc {
+        hash: data.hash,
+        receiver: data.receiver,
+        sender: w.public,
+        deadline: data.deadline,
     };
 
-    let w = match s.w.lock() {
-        Ok(guard) => guard,
-        Err(poisoned) => {
-            eprintln!("⚠️ Warning: Wallet mutex was poisoned! Recovering...");
-            poisoned.into_inner()
+    match w.create_htlc(&htlc, data.amount, data.fee, &c, &mut p) {
+        Ok(t) => {
+            println!("🔒 Created HTLC in transaction {}", t.txid().0);
+            tokio::spawn(notify_p2p_transaction(t));
+            Json(json!(htlc))
         }
-    };
+        Err(e) => {
+            eprintln!("❌ Could not create HTLC: {}", e);
+            Json(json!("{}"))
+        }
+    }
+}
 
-    let mut transactions = p.valid();
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct ClaimData {
+    htlc: Htlc,
+    secret: String,
+    #[serde(default)]
+    fee: Amount,
+}
 
-    let reward = Transaction::reward(&w.public);
-    transactions.push(reward);
+async fn claim_htlc(State(s): State<AppState>, Json(data): Json<ClaimData>) -> Json<Value> {
+    let c = s.c.lock().unwrap();
+    let mut p = s.p.lock().unwrap();
+    let w = s.w.lock().unwrap();
 
-    println!("{}", json!(transactions));
+    match w.claim_htlc(&data.htlc, data.secret.as_bytes(), data.fee, &c, &mut p) {
+        Ok(t) => {
+            println!("🔓 Claimed HTLC in transaction {}", t.txid().0);
+            tokio::spawn(notify_p2p_transaction(t.clone()));
+            Json(json!(t))
+        }
+        Err(e) => {
+            eprintln!("❌ Could not claim HTLC: {}", e);
+            Json(json!("{}"))
+        }
+    }
+}
 
-    let block = c.add(json!(transactions).to_string().as_str());
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct RefundData {
+    htlc: Htlc,
+    #[serde(default)]
+    fee: Amount,
+}
 
-    p.clear();
+async fn refund_htlc(State(s): State<AppState>, Json(data): Json<RefundData>) -> Json<Value> {
+    let c = s.c.lock().unwrap();
+    let mut p = s.p.lock().unwrap();
+    let w = s.w.lock().unwrap();
 
+    match w.refund_htlc(&data.htlc, data.fee, &c, &mut p) {
+        Ok(t) => {
+            println!("↩️ Refunded HTLC in transaction {}", t.txid().0);
+            tokio::spawn(notify_p2p_transaction(t.clone()));
+            Json(json!(t))
+        }
+        Err(e) => {
+            eprintln!("❌ Could not refund HTLC: {}", e);
+            Json(json!("{}"))
+        }
+    }
+}
+
+/// Adds this wallet's signatures to a transaction spending coins of several
+/// wallets, for the caller to pass on to the next signer or submit.
+async fn sign_transaction(
+    State(s): State<AppState>,
+    Json(mut transaction): Json<Transaction>,
+) -> Json<Value> {
+    let chain_id = s.c.lock().unwrap().chain_id();
+    let w = s.w.lock().unwrap();
+
+    match transaction.sign(&w, chain_id) {
+        Ok(()) => Json(json!(transaction)),
+        Err(e) => {
+            eprintln!("❌ Could not sign transaction: {}", e);
+            Json(json!("{}"))
+        }
+    }
+}
+
+async fn get_public_key(State(s): State<AppState>) -> Json<Value> {
+    let w = s.w.lock().unwrap();
+
+    Json(json!(w.public))
+}
+
+async fn mine(State(s): State<AppState>) -> Json<Value> {
+    let block = match mine_next(&s, None, true).await {
+        Ok(block) => block,
+        Err(e) => {
+            eprintln!("❌ Failed to add block: {}", e);
+            return Json(json!("{}"));
+        }
+    };
+
+    let c = lock(&s.c, "Blockchain");
     tokio::spawn(notify_p2p_server(c.chain.clone()));
 
     Json(json!(block))
 }
 
+/// Mines the next block on the miner's worker threads without holding the
+/// chain lock, starting over whenever a new tip arrives before a block is found.
+async fn mine_next(s: &AppState, memo: Option<String>, pool: bool) -> Result<Block, String> {
+    loop {
+        let (template, cancel) = {
+            let c = lock(&s.c, "Blockchain");
+            let mut transactions = Vec::new();
+
+            if pool {
+                let p = lock(&s.p, "Transaction Pool");
+
+                transactions = p.select(c.utxos(), MAX_BLOCK_SIZE);
+
+                println!("{}", json!(transactions));
+            }
+
+            let miner = lock(&s.w, "Wallet").public;
+            (
+                c.template(&miner, transactions, memo.as_deref()),
+                s.m.token(),
+            )
+        };
+
+        let m = s.m.clone();
+        let found = tokio::task::spawn_blocking(move || m.mine(&template, &cancel))
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let Some(block) = found else {
+            println!("⛏️ New tip arrived, restarting mining.");
+            continue;
+        };
+
+        let mut c = lock(&s.c, "Blockchain");
+        match c.submit(block.clone()) {
+            Ok(()) => {
+                // Other jobs are mining on the old tip.
+                s.m.interrupt();
+                lock(&s.p, "Transaction Pool").connect(&block);
+                return Ok(block);
+            }
+            Err(e) => println!("⛏️ Mined block is stale, restarting mining: {}", e),
+        }
+    }
+}
+
+fn lock<'a, T>(m: &'a Mutex<T>, name: &str) -> MutexGuard<'a, T> {
+    match m.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => {
+            eprintln!("⚠️ Warning: {} mutex was poisoned! Recovering...", name);
+            poisoned.into_inner() // Recover from the poisoned state
+        }
+    }
+}
+
 // notify
 
 pub async fn notify_p2p_server(chain: Vec<Block>) {
@@ -1,31 +1,118 @@
-use serde_json::json;
-
-use crate::{
-    blockchain::{block::Block, chain::Chain},
-    transaction::{pool::Pool, transaction::Transaction, wallet::Wallet},
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
 };
 
+use crate::{blockchain::block::Block, utils::time};
+
+/// Nonces a worker claims at a time. The timestamp is refreshed and
+/// cancellation is checked once per range.
+const RANGE: usize = 1 << 12;
+
+/// Proof-of-work search spread over a pool of worker threads.
 pub struct Miner {
-    chain: Chain,
-    pool: Pool,
-    wallet: Wallet,
+    threads: usize,
+    cancel: Mutex<Arc<AtomicBool>>,
 }
 
 impl Miner {
-    pub fn new() -> Self {
+    pub fn new(threads: usize) -> Self {
         Miner {
-            chain: Chain::new(),
-            pool: Pool::new(),
-            wallet: Wallet::new(),
+            threads: threads.max(1),
+            cancel: Mutex::new(Arc::new(AtomicBool::new(false))),
         }
     }
 
-    pub fn mine(&mut self) -> Chain {
-        let mut transactions = self.pool.valid();
+    /// Token that `interrupt` sets, to pass to `mine`.
+    pub fn token(&self) -> Arc<AtomicBool> {
+        self.cancel.lock().unwrap().clone()
+    }
+
+    /// Cancels every search started with a token handed out before this call.
+    pub fn interrupt(&self) {
+        let mut cancel = self.cancel.lock().unwrap();
+        cancel.store(true, Ordering::Relaxed);
+        *cancel = Arc::new(AtomicBool::new(false));
+    }
+
+    /// Searches nonces for `template` on the worker threads until one meets
+    /// its target, or returns `None` once `cancel` is set.
+    ///
+    /// This blocks the calling thread, so call it from `spawn_blocking` on the
+    /// async runtime.
+    pub fn mine(&self, template: &Block, cancel: &AtomicBool) -> Option<Block> {
+        let next = AtomicUsize::new(0);
+        let found = Mutex::new(None);
+        let done = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            for _ in 0..self.threads {
+                scope.spawn(|| {
+                    let mut block = template.clone();
+
+                    while !done.load(Ordering::Relaxed) && !cancel.load(Ordering::Relaxed) {
+                        let start = next.fetch_add(RANGE, Ordering::Relaxed);
+
+                        block.timestamp = time();
+
+                        for nonce in start..start + RANGE {
+                            block.nonce = nonce;
+
+                            if block.proof() {
+                                done.store(true, Ordering::Relaxed);
+                                found.lock().unwrap().get_or_insert(block);
+                                return;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        found.into_inner().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::target;
+    use std::time::Duration;
+
+    fn template(bits: u32) -> Block {
+        let mut block = Block::new(Block::genesis(), Vec::new(), Some("foo"));
+        block.bits = bits;
+        block
+    }
+
+    #[test]
+    fn mine() {
+        let m = Miner::new(4);
+        let b = m
+            .mine(&template(target::from_difficulty(128)), &m.token())
+            .unwrap();
+
+        assert!(b.proof());
+        assert_eq!(b.bits, target::from_difficulty(128));
+        assert_eq!(b.prev, Block::genesis().hash());
+    }
+
+    #[test]
+    fn interrupt() {
+        let m = Miner::new(2);
+        let cancel = m.token();
+
+        thread::scope(|scope| {
+            let search = scope.spawn(|| m.mine(&template(0x03000001), &cancel));
+            thread::sleep(Duration::from_millis(50));
+            m.interrupt();
 
-        let reward = Transaction::reward(&self.wallet.public);
-        transactions.push(&reward);
+            assert!(search.join().unwrap().is_none());
+        });
 
-        self.chain.add(json!(transactions).as_str().unwrap());
+        assert!(!m.token().load(Ordering::Relaxed));
     }
 }
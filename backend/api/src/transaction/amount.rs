@@ -0,0 +1,187 @@
+use std::fmt;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Number of decimal places a coin can be split into.
+pub const DECIMALS: usize = 8;
+/// Base units in one whole coin.
+pub const COIN: u64 = 10u64.pow(DECIMALS as u32);
+
+/// A quantity of coins, counted in indivisible base units.
+///
+/// Arithmetic is checked, so amounts can neither overflow nor go negative.
+/// On the API amounts are decimal strings such as `"12.5"`, while binary
+/// encodings carry the raw number of units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Amount(u64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+    pub const MAX: Amount = Amount(u64::MAX);
+
+    pub const fn from_units(units: u64) -> Self {
+        Amount(units)
+    }
+
+    pub const fn from_coins(coins: u64) -> Self {
+        Amount(coins * COIN)
+    }
+
+    pub fn units(&self) -> u64 {
+        self.0
+    }
+
+    pub fn checked_add(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_add(rhs.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_sub(rhs.0).map(Amount)
+    }
+
+    /// Total of `amounts`, or `None` if it overflows.
+    pub fn sum(amounts: impl IntoIterator<Item = Amount>) -> Option<Amount> {
+        amounts
+            .into_iter()
+            .try_fold(Amount::ZERO, |total, a| total.checked_add(a))
+    }
+
+    /// Parses a decimal number of coins such as `12`, `0.5` or `1.00000001`.
+    pub fn parse(s: &str) -> Result<Amount, String> {
+        let (whole, frac) = s.trim().split_once('.').unwrap_or((s.trim(), ""));
+        let digits = |d: &str| d.bytes().all(|b| b.is_ascii_digit());
+
+        if whole.is_empty() || !digits(whole) || !digits(frac) || s.trim().ends_with('.') {
+            return Err(format!("Invalid amount {:?}.", s));
+        }
+        if frac.len() > DECIMALS {
+            return Err(format!("Amount has more than {} decimals.", DECIMALS));
+        }
+
+        let overflow = || format!("Amount {:?} is too large.", s);
+        let whole: u64 = whole.parse().map_err(|_| overflow())?;
+        let frac: u64 = format!("{:0<width$}", frac, width = DECIMALS)
+            .parse()
+            .unwrap();
+
+        whole
+            .checked_mul(COIN)
+            .and_then(|w| w.checked_add(frac))
+            .map(Amount)
+            .ok_or_else(overflow)
+    }
+}
+
+/// Writes the amount in coins with `DECIMALS` places, or fewer when a
+/// precision is given (`{:.2}`), rounding down.
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let precision = f.precision().unwrap_or(DECIMALS).min(DECIMALS);
+        let whole = self.0 / COIN;
+
+        if precision == 0 {
+            return write!(f, "{}", whole);
+        }
+
+        let frac = format!("{:0width$}", self.0 % COIN, width = DECIMALS);
+        write!(f, "{}.{}", whole, &frac[..precision])
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&self.to_string())
+        } else {
+            serializer.serialize_u64(self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        if !d.is_human_readable() {
+            return u64::deserialize(d).map(Amount);
+        }
+
+        struct Visitor;
+
+        impl de::Visitor<'_> for Visitor {
+            type Value = Amount;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a decimal string or a whole number of coins")
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<Amount, E> {
+                Amount::parse(s).map_err(E::custom)
+            }
+
+            fn visit_u64<E: de::Error>(self, n: u64) -> Result<Amount, E> {
+                n.checked_mul(COIN)
+                    .map(Amount)
+                    .ok_or_else(|| E::custom("Amount is too large."))
+            }
+        }
+
+        d.deserialize_any(Visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(Amount::parse("12"), Ok(Amount::from_coins(12)));
+        assert_eq!(Amount::parse("0.5"), Ok(Amount::from_units(COIN / 2)));
+        assert_eq!(
+            Amount::parse("1.00000001"),
+            Ok(Amount::from_units(COIN + 1))
+        );
+
+        // ❌ Malformed, too precise or too large
+        for s in ["", ".5", "1.", "-1", "1e3", "0.000000001", "184467440738"] {
+            assert!(Amount::parse(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn display() {
+        let a = Amount::from_units(12 * COIN + 34_567_890);
+
+        assert_eq!(a.to_string(), "12.34567890");
+        assert_eq!(format!("{:.2}", a), "12.34");
+        assert_eq!(format!("{:.0}", a), "12");
+        assert_eq!(Amount::parse(&a.to_string()), Ok(a));
+    }
+
+    // ✅ Test: Arithmetic never wraps around
+    #[test]
+    fn checked() {
+        let one = Amount::from_coins(1);
+
+        assert_eq!(one.checked_sub(Amount::from_coins(2)), None);
+        assert_eq!(Amount::MAX.checked_add(one), None);
+        assert_eq!(Amount::sum([one, one]), Some(Amount::from_coins(2)));
+        assert_eq!(Amount::sum([Amount::MAX, one]), None);
+    }
+
+    #[test]
+    fn serde() {
+        let a = Amount::parse("2.5").unwrap();
+
+        assert_eq!(serde_json::json!(a), serde_json::json!("2.50000000"));
+        assert_eq!(serde_json::from_str::<Amount>("\"2.5\"").unwrap(), a);
+        assert_eq!(
+            serde_json::from_str::<Amount>("3").unwrap(),
+            Amount::from_coins(3)
+        );
+        assert!(serde_json::from_str::<Amount>("2.5").is_err());
+
+        let bytes = bincode::serialize(&a).unwrap();
+        assert_eq!(bytes, (COIN * 5 / 2).to_le_bytes());
+        assert_eq!(bincode::deserialize::<Amount>(&bytes).unwrap(), a);
+    }
+}
@@ -0,0 +1,147 @@
+use secp256k1::{ecdsa::Signature, PublicKey};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::hash::Hash;
+
+use super::{
+    output::Lock,
+    script::{Op, Script},
+};
+
+/// Hash-time-locked contract: coins the receiver can claim by revealing the
+/// secret whose hash is `hash`, and the sender can take back from
+/// `deadline` on.
+///
+/// For an atomic swap between two chains, each side locks coins to the
+/// other under the same hash, and the side that picked the secret gives
+/// itself the later deadline. Claiming its coins reveals the secret to the
+/// other side, which then has until its own deadline to claim in turn.
+/// Claiming stays possible until the sender refunds, so receivers should
+/// not wait for the deadline.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Htlc {
+    /// `Hash::new` of the secret.
+    pub hash: Hash,
+    pub receiver: PublicKey,
+    pub sender: PublicKey,
+    /// Block height, or Unix time from `LOCKTIME_THRESHOLD` on, from which
+    /// the sender may refund.
+    pub deadline: u64,
+}
+
+impl Htlc {
+    /// Lock of the coins held by the contract.
+    pub fn lock(&self) -> Result<Lock, String> {
+        let hash = self
+            .hash
+            .to_bytes()
+            .ok_or("hash is not a SHA-256 digest.")?;
+
+        Ok(Lock::Script(Script(vec![
+            Op::If,
+            Op::Sha256,
+            Op::Push(hash.to_vec()),
+            Op::EqualVerify,
+            Op::Push(self.receiver.serialize().to_vec()),
+            Op::Else,
+            Op::Num(self.deadline),
+            Op::CheckLockTimeVerify,
+            Op::Push(self.sender.serialize().to_vec()),
+            Op::EndIf,
+            Op::CheckSig,
+        ])))
+    }
+
+    /// Unlocking script for the receiver, revealing `secret`.
+    pub fn claim(&self, signature: &Signature, secret: &[u8]) -> Script {
+        Script(vec![
+            Op::push(signature.serialize_der().to_vec()),
+            Op::push(secret.to_vec()),
+            Op::Num(1),
+        ])
+    }
+
+    /// Unlocking script for the sender, in a transaction whose `lock_time`
+    /// is at least `deadline`.
+    pub fn refund(&self, signature: &Signature) -> Script {
+        Script(vec![
+            Op::push(signature.serialize_der().to_vec()),
+            Op::Num(0),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{
+        amount::Amount,
+        input::OutPoint,
+        output::Output,
+        transaction::{Transaction, DEFAULT_CHAIN_ID, LOCKTIME_THRESHOLD},
+        wallet::Wallet,
+    };
+
+    /// Spend of a coin held by `htlc`, unlocked by `wallet` with `unlock`.
+    fn spend(
+        htlc: &Htlc,
+        wallet: &Wallet,
+        lock_time: u64,
+        unlock: impl Fn(&Signature) -> Script,
+    ) -> Transaction {
+        let coin = (
+            OutPoint {
+                txid: Hash::blank(),
+                index: 0,
+            },
+            Output::new(htlc.lock().unwrap(), Amount::from_coins(5)),
+        );
+        let mut t = Transaction::build(
+            &[coin],
+            vec![Output::new(&wallet.public, Amount::from_coins(5))],
+        );
+        t.lock_time = lock_time;
+
+        let signature = wallet.sign(t.sighash(DEFAULT_CHAIN_ID));
+        t.inputs[0].script = unlock(&signature);
+        t
+    }
+
+    #[test]
+    fn claim() {
+        let (sender, receiver) = (Wallet::new(), Wallet::new());
+        let htlc = Htlc {
+            hash: Hash::new("secret").unwrap(),
+            receiver: receiver.public,
+            sender: sender.public,
+            deadline: 10,
+        };
+
+        let claim =
+            |w: &Wallet, secret: &str| spend(&htlc, w, 0, |sig| htlc.claim(sig, secret.as_bytes()));
+        assert!(claim(&receiver, "secret").verify(DEFAULT_CHAIN_ID).is_ok());
+        // ❌ A wrong secret, or the right one revealed to someone else
+        assert!(claim(&receiver, "guess").verify(DEFAULT_CHAIN_ID).is_err());
+        assert!(claim(&sender, "secret").verify(DEFAULT_CHAIN_ID).is_err());
+    }
+
+    // ✅ Test: Only the sender can refund, and only from the deadline on
+    #[test]
+    fn refund() {
+        let (sender, receiver) = (Wallet::new(), Wallet::new());
+        let htlc = Htlc {
+            hash: Hash::new("secret").unwrap(),
+            receiver: receiver.public,
+            sender: sender.public,
+            deadline: 10,
+        };
+
+        let refund = |w: &Wallet, lock_time| spend(&htlc, w, lock_time, |sig| htlc.refund(sig));
+        assert!(refund(&sender, 10).verify(DEFAULT_CHAIN_ID).is_ok());
+        assert!(refund(&sender, 9).verify(DEFAULT_CHAIN_ID).is_err());
+        assert!(refund(&sender, LOCKTIME_THRESHOLD)
+            .verify(DEFAULT_CHAIN_ID)
+            .is_err());
+        assert!(refund(&receiver, 10).verify(DEFAULT_CHAIN_ID).is_err());
+    }
+}
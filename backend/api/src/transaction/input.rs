@@ -1,29 +1,63 @@
-use bincode::serialize;
-use secp256k1::{ecdsa::Signature, PublicKey};
+use secp256k1::{ecdsa::Signature, Message};
 use serde::{Deserialize, Serialize};
 
 use crate::utils::hash::Hash;
-use crate::utils::time;
 
-use super::wallet::Wallet;
+use super::{
+    output::{Lock, Timelock},
+    script::{Script, Spend},
+};
 
-use super::output::Output;
+/// Points at output `index` of the transaction with hash `txid`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OutPoint {
+    pub txid: Hash,
+    pub index: usize,
+}
 
-#[derive(Deserialize, Serialize, Debug, Copy, Clone)]
+/// Spends one unspent output, which must be locked by `lock`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct Input {
-    pub timestamp: u64,
-    pub amount: f64,
-    pub address: PublicKey,
-    pub signature: Signature,
+    pub outpoint: OutPoint,
+    pub lock: Lock,
+    /// Signature over the `sighash` of the transaction by each key of
+    /// `lock`, in order, or `None` where that key has not signed.
+    pub signatures: Vec<Option<Signature>>,
+    /// Unlocking script for a `Lock::Script`.
+    #[serde(default)]
+    pub script: Script,
+    /// Timelock the input waits for on top of that of its coin, so that a
+    /// script can check it with `CheckSequenceVerify`.
+    #[serde(default)]
+    pub timelock: Timelock,
 }
 
 impl Input {
-    pub fn new(sender: &Wallet, outputs: &Vec<Output>) -> Self {
+    pub fn new(outpoint: OutPoint, lock: Lock) -> Self {
         Input {
-            timestamp: time(),
-            amount: sender.balance,
-            address: sender.public,
-            signature: sender.sign(Hash::new(serialize(outputs).unwrap()).unwrap()),
+            outpoint,
+            signatures: vec![None; lock.keys().len()],
+            lock,
+            script: Script::default(),
+            timelock: Timelock::None,
+        }
+    }
+
+    /// Whether the signatures, or the unlocking script for a script lock,
+    /// satisfy the lock of the spent coin. `message` is the sighash of the
+    /// transaction and `lock_time` its lock time. The other of the two must
+    /// be empty, since both are part of the txid.
+    pub fn verify(&self, message: &Message, lock_time: u64) -> bool {
+        match &self.lock {
+            Lock::Script(script) => {
+                let spend = Spend {
+                    message,
+                    lock_time,
+                    timelock: self.timelock,
+                };
+                self.signatures.is_empty() && script.run(&self.script, &spend).is_ok()
+            }
+            lock => self.script.0.is_empty() && lock.verify(message, &self.signatures),
         }
     }
 }
@@ -1,5 +1,9 @@
-mod input;
-mod output;
+pub mod amount;
+pub mod htlc;
+pub mod input;
+pub mod output;
 pub mod pool;
+pub mod script;
+#[allow(clippy::module_inception)]
 pub mod transaction;
 pub mod wallet;
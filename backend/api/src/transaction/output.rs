@@ -1,17 +1,227 @@
-use secp256k1::PublicKey;
+use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+use super::{amount::Amount, script::Script};
+
+/// Most keys a multisignature lock may list.
+pub const MAX_MULTISIG_KEYS: usize = 16;
+
+/// Who may spend an output.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Lock {
+    /// Spendable with a signature by the key.
+    Key(PublicKey),
+    /// Spendable with signatures by `threshold` of the distinct `keys`.
+    Multisig {
+        threshold: usize,
+        keys: Vec<PublicKey>,
+    },
+    /// Spendable by an input whose unlocking script makes it succeed.
+    Script(Script),
+}
+
+impl Lock {
+    pub fn keys(&self) -> &[PublicKey] {
+        match self {
+            Lock::Key(key) => std::slice::from_ref(key),
+            Lock::Multisig { keys, .. } => keys,
+            Lock::Script(_) => &[],
+        }
+    }
+
+    pub fn threshold(&self) -> usize {
+        match self {
+            Lock::Key(_) => 1,
+            Lock::Multisig { threshold, .. } => *threshold,
+            Lock::Script(_) => 0,
+        }
+    }
+
+    /// Checks that a multisignature lock needs between one and all of at
+    /// most `MAX_MULTISIG_KEYS` distinct keys, and that a script is within
+    /// its limits.
+    pub fn check(&self) -> Result<(), String> {
+        if let Lock::Script(script) = self {
+            return script.check();
+        }
+        let keys = self.keys();
+
+        if keys.len() > MAX_MULTISIG_KEYS {
+            return Err(format!("lock lists more than {} keys.", MAX_MULTISIG_KEYS));
+        }
+        if self.threshold() == 0 || self.threshold() > keys.len() {
+            return Err("lock threshold must be between 1 and the number of keys.".to_string());
+        }
+        if (1..keys.len()).any(|i| keys[..i].contains(&keys[i])) {
+            return Err("lock lists a key twice.".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Whether `signatures`, holding one slot per key in order, has exactly
+    /// `threshold` signatures over `message` and no invalid ones, so that no
+    /// one can drop a spare signature to change the txid. Scripts take an
+    /// unlocking script instead, see `Input::verify`.
+    pub fn verify(&self, message: &Message, signatures: &[Option<Signature>]) -> bool {
+        !matches!(self, Lock::Script(_))
+            && self.count_signatures(message, signatures) == Some(self.threshold())
+    }
+
+    /// Number of filled slots in `signatures`, or `None` if there is not one
+    /// slot per key or any slot holds something other than a signature over
+    /// `message` by its key.
+    pub fn count_signatures(
+        &self,
+        message: &Message,
+        signatures: &[Option<Signature>],
+    ) -> Option<usize> {
+        let s = Secp256k1::new();
+
+        if signatures.len() != self.keys().len() {
+            return None;
+        }
+
+        self.keys()
+            .iter()
+            .zip(signatures)
+            .filter_map(|(key, sig)| sig.map(|sig| (key, sig)))
+            .try_fold(0, |n, (key, sig)| {
+                s.verify_ecdsa(message, &sig, key).ok().map(|_| n + 1)
+            })
+    }
+}
+
+impl From<&PublicKey> for Lock {
+    fn from(key: &PublicKey) -> Self {
+        Lock::Key(*key)
+    }
+}
+
+/// When an output becomes spendable, on top of its `Lock`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Timelock {
+    #[default]
+    None,
+    /// From the block at this height on.
+    Height(usize),
+    /// From the first block with at least this Unix timestamp on.
+    Time(u64),
+    /// From this many blocks after the block that created the output.
+    Blocks(usize),
+    /// From this many seconds after the block that created the output.
+    Seconds(u64),
+}
+
+impl Timelock {
+    /// Whether an output created by the block at `created_height` with
+    /// timestamp `created_time` can be spent in a block at `height` with
+    /// timestamp `time`.
+    pub fn is_open(
+        &self,
+        created_height: usize,
+        created_time: u64,
+        height: usize,
+        time: u64,
+    ) -> bool {
+        match *self {
+            Timelock::None => true,
+            Timelock::Height(h) => height >= h,
+            Timelock::Time(t) => time >= t,
+            Timelock::Blocks(n) => height >= created_height.saturating_add(n),
+            Timelock::Seconds(s) => time >= created_time.saturating_add(s),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Output {
-    pub address: PublicKey,
-    pub amount: f64,
+    pub lock: Lock,
+    pub amount: Amount,
+    #[serde(default)]
+    pub timelock: Timelock,
 }
 
 impl Output {
-    pub fn new(address: &PublicKey, amount: f64) -> Self {
+    pub fn new(lock: impl Into<Lock>, amount: Amount) -> Self {
         Output {
-            address: address.to_owned(),
+            lock: lock.into(),
             amount,
+            timelock: Timelock::None,
         }
     }
+
+    /// The same output, spendable only once `timelock` has passed.
+    pub fn with_timelock(mut self, timelock: Timelock) -> Self {
+        self.timelock = timelock;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{transaction::wallet::Wallet, utils::hash::Hash};
+
+    #[test]
+    fn check() {
+        let keys: Vec<PublicKey> = (0..3).map(|_| Wallet::new().public).collect();
+        let multisig = |threshold, keys: &[PublicKey]| Lock::Multisig {
+            threshold,
+            keys: keys.to_vec(),
+        };
+
+        assert!(Lock::Key(keys[0]).check().is_ok());
+        assert!(multisig(2, &keys).check().is_ok());
+        assert!(multisig(3, &keys).check().is_ok());
+
+        // ❌ Spendable by anyone, by no one, or listing a key twice
+        assert!(multisig(0, &keys).check().is_err());
+        assert!(multisig(4, &keys).check().is_err());
+        assert!(multisig(1, &[keys[0], keys[0]]).check().is_err());
+        assert!(multisig(1, &vec![keys[0]; MAX_MULTISIG_KEYS + 1])
+            .check()
+            .is_err());
+    }
+
+    #[test]
+    fn timelock() {
+        // Output created by block 10, at time 1000
+        let open = |t: Timelock, height, time| t.is_open(10, 1_000, height, time);
+
+        assert!(open(Timelock::None, 0, 0));
+        assert!(open(Timelock::Height(12), 12, 0));
+        assert!(!open(Timelock::Height(12), 11, u64::MAX));
+        assert!(open(Timelock::Time(2_000), 11, 2_000));
+        assert!(!open(Timelock::Time(2_000), usize::MAX, 1_999));
+        assert!(open(Timelock::Blocks(5), 15, 0));
+        assert!(!open(Timelock::Blocks(5), 14, u64::MAX));
+        assert!(open(Timelock::Seconds(60), 11, 1_060));
+        assert!(!open(Timelock::Seconds(60), usize::MAX, 1_059));
+    }
+
+    // ✅ Test: A 2-of-3 lock takes two valid signatures in the right slots
+    #[test]
+    fn verify() {
+        let wallets: Vec<Wallet> = (0..3).map(|_| Wallet::new()).collect();
+        let lock = Lock::Multisig {
+            threshold: 2,
+            keys: wallets.iter().map(|w| w.public).collect(),
+        };
+
+        let hash = Hash::new("treasury").unwrap();
+        let message = Message::from_digest(hash.to_bytes().unwrap());
+        let sig = |i: usize| Some(wallets[i].sign(hash.clone()));
+
+        assert!(lock.verify(&message, &[sig(0), None, sig(2)]));
+        assert!(!lock.verify(&message, &[sig(0), None, None]));
+        assert!(!lock.verify(&message, &[sig(1), sig(0), None]));
+        assert!(!lock.verify(&message, &[sig(0), sig(1)]));
+        // ❌ Enough signatures, but one in the wrong slot
+        assert!(!lock.verify(&message, &[sig(0), sig(0), sig(2)]));
+        // ❌ More signatures than the threshold
+        assert!(!lock.verify(&message, &[sig(0), sig(1), sig(2)]));
+        // ❌ Scripts are never satisfied by signature slots alone
+        assert!(!Lock::Script(Script::default()).verify(&message, &[]));
+    }
 }
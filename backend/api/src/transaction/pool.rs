@@ -1,72 +1,512 @@
-use secp256k1::PublicKey;
-use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+};
 
-use super::transaction::Transaction;
+use bincode::serialize;
 
-#[derive(Serialize, Deserialize, Debug)]
+use crate::{
+    blockchain::{block::Block, chain::Reorg, utxo::UtxoSet},
+    utils::{hash::Hash, time},
+};
+
+use super::{amount::Amount, input::OutPoint, output::Lock, transaction::Transaction};
+
+/// Most bytes of transactions the pool holds before evicting the cheapest.
+pub const MAX_POOL_SIZE: usize = 1 << 20;
+/// Seconds a transaction may wait in the pool before it is dropped.
+pub const MAX_AGE: u64 = 24 * 60 * 60;
+/// Most bytes of pool transactions a block template takes.
+pub const MAX_BLOCK_SIZE: usize = 1 << 18;
+
+/// A pooled transaction with what it pays and when it arrived.
+#[derive(Debug, Clone)]
+struct Entry {
+    transaction: Transaction,
+    size: usize,
+    fee: Amount,
+    added: u64,
+}
+
+impl Entry {
+    /// Compares fees per byte without dividing.
+    fn cmp_rate(&self, other: &Entry) -> Ordering {
+        let a = self.fee.units() as u128 * other.size as u128;
+        let b = other.fee.units() as u128 * self.size as u128;
+        a.cmp(&b)
+    }
+}
+
+/// Transactions waiting to be mined, indexed by txid, by sender and by the
+/// coins they spend.
+///
+/// A transaction replaces the pending ones spending any of the same coins,
+/// which is how a sender's later payments are added to its pending
+/// transaction. The pool is kept under `max_size` bytes by evicting the entries
+/// paying the lowest fee per byte, and entries older than `max_age` seconds
+/// are dropped.
+#[derive(Debug)]
 pub struct Pool {
-    pub transactions: Vec<Transaction>,
+    pub max_size: usize,
+    pub max_age: u64,
+    entries: HashMap<Hash, Entry>,
+    senders: HashMap<Lock, Hash>,
+    spent: HashMap<OutPoint, Hash>,
+    size: usize,
 }
 
 impl Pool {
     pub fn new() -> Self {
         Pool {
-            transactions: Vec::new(),
+            max_size: MAX_POOL_SIZE,
+            max_age: MAX_AGE,
+            entries: HashMap::new(),
+            senders: HashMap::new(),
+            spent: HashMap::new(),
+            size: 0,
         }
     }
 
-    pub fn update(&mut self, transaction: Transaction) {
-        match transaction.verify() {
-            Ok(_) => {
-                match self
-                    .transactions
-                    .iter()
-                    .enumerate()
-                    .find(|(i, t)| t.id == transaction.id)
-                {
-                    Some((i, _)) => {
-                        self.transactions[i] = transaction;
-                    }
-                    None => self.transactions.push(transaction),
-                }
+    pub fn transactions(&self) -> Vec<&Transaction> {
+        self.entries.values().map(|e| &e.transaction).collect()
+    }
+
+    /// The latest pending transaction spending coins locked by `lock`, if any.
+    pub fn check(&self, lock: impl Into<Lock>) -> Option<&Transaction> {
+        self.senders
+            .get(&lock.into())
+            .and_then(|txid| self.entries.get(txid))
+            .map(|e| &e.transaction)
+    }
+
+    /// Whether a pending transaction spends the coin at `outpoint`.
+    pub fn spends(&self, outpoint: &OutPoint) -> bool {
+        self.spent.contains_key(outpoint)
+    }
+
+    /// Adds `transaction`, replacing any pending one spending the same coins.
+    ///
+    /// It must be signed, spend unspent coins in `utxos` of its senders
+    /// worth at least its outputs, and be minable in the next block as far
+    /// as lock times go. It is refused if the pool is full of transactions
+    /// paying more per byte.
+    pub fn update(&mut self, transaction: Transaction, utxos: &UtxoSet) -> Result<(), String> {
+        if transaction.is_coinbase() {
+            return Err("coinbase transactions cannot be pooled.".to_string());
+        }
+
+        // Coins already spent by a confirmed copy are gone, so replays end here.
+        if !utxos.owned(&transaction) {
+            return Err("inputs are not unspent coins of their senders.".to_string());
+        }
+
+        let fee = utxos
+            .fee(&transaction)
+            .ok_or("outputs greater than inputs.")?;
+
+        if transaction.verify(utxos.chain_id()).is_err() {
+            return Err("signature does not match.".to_string());
+        }
+
+        for output in transaction.outputs.iter() {
+            output.lock.check()?;
+        }
+
+        let now = time();
+        if !utxos.unlocked(&transaction, utxos.height() + 1, now) {
+            return Err("transaction is still time-locked.".to_string());
+        }
+
+        self.expire(now);
+
+        let mut removed = Vec::new();
+        for input in transaction.inputs.iter() {
+            if let Some(old) = self.spent.get(&input.outpoint).cloned() {
+                removed.extend(self.remove(&old));
             }
-            Err(_) => (),
         }
+
+        let txid = transaction.txid();
+        self.insert(Entry {
+            size: serialize(&transaction).unwrap().len(),
+            fee,
+            added: now,
+            transaction,
+        });
+        removed.extend(self.evict());
+
+        if self.entries.contains_key(&txid) {
+            return Ok(());
+        }
+
+        // Nothing changes when the transaction itself does not fit.
+        for entry in removed {
+            if entry.transaction.txid() != txid {
+                self.insert(entry);
+            }
+        }
+        Err("pool is full of transactions paying higher fees.".to_string())
+    }
+
+    fn insert(&mut self, entry: Entry) {
+        let txid = entry.transaction.txid();
+
+        self.size += entry.size;
+        for input in entry.transaction.inputs.iter() {
+            self.senders.insert(input.lock.clone(), txid.clone());
+            self.spent.insert(input.outpoint.clone(), txid.clone());
+        }
+        self.entries.insert(txid, entry);
+    }
+
+    fn remove(&mut self, txid: &Hash) -> Option<Entry> {
+        let entry = self.entries.remove(txid)?;
+        self.size -= entry.size;
+
+        for input in entry.transaction.inputs.iter() {
+            if self.senders.get(&input.lock) == Some(txid) {
+                self.senders.remove(&input.lock);
+            }
+            self.spent.remove(&input.outpoint);
+        }
+
+        Some(entry)
     }
 
-    pub fn check(&mut self, address: PublicKey) -> Option<&mut Transaction> {
-        self.transactions
-            .iter_mut()
-            .find(|t| t.input.unwrap().address == address)
+    /// Drops the entries paying the least per byte until the pool fits
+    /// `max_size`, returning them.
+    fn evict(&mut self) -> Vec<Entry> {
+        let mut evicted = Vec::new();
+
+        while self.size > self.max_size {
+            let cheapest = self
+                .entries
+                .iter()
+                .min_by(|a, b| a.1.cmp_rate(b.1))
+                .map(|(txid, _)| txid.clone());
+
+            match cheapest {
+                Some(txid) => evicted.extend(self.remove(&txid)),
+                None => break,
+            };
+        }
+
+        evicted
     }
 
-    /// 🔹 Filter valid transactions from the pool
-    pub fn valid(&self) -> Vec<Transaction> {
-        self.transactions
+    /// Drops the entries that have waited longer than `max_age` at time `now`.
+    pub fn expire(&mut self, now: u64) {
+        let expired: Vec<Hash> = self
+            .entries
             .iter()
-            .filter(|transaction| {
-                let output_total: f64 = transaction.outputs.iter().map(|o| o.amount).sum();
-
-                // 🔥 Check if input amount matches output total
-                if transaction.input.unwrap().amount != output_total {
-                    println!(
-                        "❌ Invalid transaction from {}",
-                        transaction.input.unwrap().address
-                    );
+            .filter(|(_, e)| e.added.saturating_add(self.max_age) < now)
+            .map(|(txid, _)| txid.clone())
+            .collect();
+
+        for txid in expired.iter() {
+            self.remove(txid);
+        }
+    }
+
+    /// 🔹 Picks transactions for a block template, highest fee per byte
+    /// first, up to `max_bytes`. Entries whose coins in `utxos` have since
+    /// been spent or that are time-locked again after a reorganisation are
+    /// skipped, as are ones conflicting with an earlier pick.
+    pub fn select(&self, utxos: &UtxoSet, max_bytes: usize) -> Vec<Transaction> {
+        let mut entries: Vec<&Entry> = self.entries.values().collect();
+        entries.sort_by(|a, b| b.cmp_rate(a));
+
+        let (height, now) = (utxos.height() + 1, time());
+
+        let mut spent = HashSet::new();
+        let mut size = 0;
+
+        entries
+            .into_iter()
+            .filter(|e| {
+                let inputs = &e.transaction.inputs;
+
+                if size + e.size > max_bytes
+                    || inputs.iter().any(|i| spent.contains(&i.outpoint))
+                    || utxos.fee(&e.transaction).is_none()
+                    || !utxos.unlocked(&e.transaction, height, now)
+                {
                     return false;
                 }
 
-                // 🔥 Verify transaction signature
-                match transaction.verify() {
-                    Ok(_) => true,
-                    Err(_) => false,
-                }
+                spent.extend(inputs.iter().map(|i| i.outpoint.clone()));
+                size += e.size;
+                true
             })
-            .cloned()
+            .map(|e| e.transaction.clone())
             .collect()
     }
 
+    /// Drops the transactions `block` confirmed and any spending the same
+    /// coins as one of them.
+    pub fn connect(&mut self, block: &Block) {
+        let confirmed: HashSet<Hash> = block.transactions.iter().map(|t| t.txid()).collect();
+        let spent: HashSet<&OutPoint> = block
+            .transactions
+            .iter()
+            .flat_map(|t| t.inputs.iter().map(|i| &i.outpoint))
+            .collect();
+
+        let stale: Vec<Hash> = self
+            .entries
+            .iter()
+            .filter(|(txid, e)| {
+                confirmed.contains(*txid)
+                    || e.transaction
+                        .inputs
+                        .iter()
+                        .any(|i| spent.contains(&i.outpoint))
+            })
+            .map(|(txid, _)| txid.clone())
+            .collect();
+
+        for txid in stale.iter() {
+            self.remove(txid);
+        }
+    }
+
+    /// Takes back the transactions of a `block` that left the chain, as far
+    /// as they are still valid against `utxos`.
+    pub fn disconnect(&mut self, block: &Block, utxos: &UtxoSet) {
+        for t in block.transactions.iter().filter(|t| !t.is_coinbase()) {
+            if let Err(e) = self.update(t.clone(), utxos) {
+                println!("🗑️ Dropped transaction of orphaned block: {}", e);
+            }
+        }
+    }
+
+    /// Follows a reorganisation, given the coins of the chain after it.
+    pub fn reorg(&mut self, reorg: &Reorg, utxos: &UtxoSet) {
+        for block in reorg.disconnected.iter() {
+            self.disconnect(block, utxos);
+        }
+        for block in reorg.connected.iter() {
+            self.connect(block);
+        }
+    }
+
     pub fn clear(&mut self) {
-        self.transactions = Vec::new();
+        self.entries.clear();
+        self.senders.clear();
+        self.spent.clear();
+        self.size = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        blockchain::chain::Chain,
+        transaction::{
+            output::{Output, Timelock},
+            transaction::DEFAULT_CHAIN_ID,
+            wallet::Wallet,
+        },
+    };
+
+    /// Chain paying one block reward to each of `n` wallets.
+    fn funded(n: usize) -> (Chain, Vec<Wallet>) {
+        let mut c = Chain::new();
+        let wallets: Vec<Wallet> = (0..n).map(|_| Wallet::new()).collect();
+
+        for w in wallets.iter() {
+            c.add(&w.public, Vec::new(), None).unwrap();
+        }
+        (c, wallets)
+    }
+
+    fn send(w: &mut Wallet, fee: u64, c: &Chain, p: &mut Pool) -> Result<Transaction, String> {
+        let to = Wallet::new().public;
+        w.send(&to, Amount::from_coins(1), Amount::from_coins(fee), c, p)
+    }
+
+    #[test]
+    fn select() {
+        let (c, mut wallets) = funded(3);
+        let mut p = Pool::new();
+
+        for (w, fee) in wallets.iter_mut().zip([1, 3, 2]) {
+            send(w, fee, &c, &mut p).unwrap();
+        }
+
+        let fees: Vec<Amount> = p
+            .select(c.utxos(), MAX_BLOCK_SIZE)
+            .iter()
+            .map(|t| c.utxos().fee(t).unwrap())
+            .collect();
+        assert_eq!(fees, [3, 2, 1].map(Amount::from_coins));
+
+        // ✅ Only what fits, best paying first
+        // Signatures vary in length, so leave room for the longest of them.
+        let size = p
+            .transactions()
+            .into_iter()
+            .map(|t| serialize(t).unwrap().len())
+            .max()
+            .unwrap();
+        let picked = p.select(c.utxos(), size);
+        assert_eq!(picked.len(), 1);
+        assert_eq!(c.utxos().fee(&picked[0]), Some(Amount::from_coins(3)));
+    }
+
+    // ✅ Test: A full pool drops whatever pays the least per byte
+    #[test]
+    fn evict() {
+        let (c, mut wallets) = funded(4);
+        let mut p = Pool::new();
+
+        let first = send(&mut wallets[0], 2, &c, &mut p).unwrap();
+        // Room for two transactions, whatever the length of their signatures
+        p.max_size = serialize(&first).unwrap().len() * 5 / 2;
+
+        send(&mut wallets[1], 1, &c, &mut p).unwrap();
+        send(&mut wallets[2], 3, &c, &mut p).unwrap();
+
+        let mut fees: Vec<Amount> = p
+            .transactions()
+            .into_iter()
+            .map(|t| c.utxos().fee(t).unwrap())
+            .collect();
+        fees.sort();
+        assert_eq!(fees, [2, 3].map(Amount::from_coins));
+
+        // ❌ Paying less than everything already pooled
+        assert!(send(&mut wallets[3], 1, &c, &mut p).is_err());
+        assert_eq!(p.transactions().len(), 2);
+    }
+
+    // ✅ Test: A replacement that does not fit leaves the pending transaction in place
+    #[test]
+    fn evict_replacement() {
+        let (c, mut wallets) = funded(2);
+        let mut p = Pool::new();
+
+        let pending = send(&mut wallets[0], 2, &c, &mut p).unwrap();
+        send(&mut wallets[1], 3, &c, &mut p).unwrap();
+        p.max_size = p.size;
+
+        // Adding a payment makes the transaction bigger without paying more.
+        assert!(send(&mut wallets[0], 0, &c, &mut p).is_err());
+        assert_eq!(p.check(&wallets[0].public), Some(&pending));
+        assert_eq!(p.transactions().len(), 2);
+        assert_eq!(p.size, p.max_size);
+    }
+
+    #[test]
+    fn expire() {
+        let (c, mut wallets) = funded(1);
+        let mut p = Pool::new();
+
+        send(&mut wallets[0], 0, &c, &mut p).unwrap();
+
+        p.expire(time());
+        assert_eq!(p.transactions().len(), 1);
+
+        p.expire(time() + MAX_AGE + 1);
+        assert!(p.transactions().is_empty());
+        assert!(p.check(&wallets[0].public).is_none());
+    }
+
+    // ✅ Test: Confirmed transactions cannot be pooled again
+    #[test]
+    fn replay() {
+        let (mut c, mut wallets) = funded(1);
+        let mut p = Pool::new();
+
+        let t = send(&mut wallets[0], 1, &c, &mut p).unwrap();
+
+        let block = c.add(&Wallet::new().public, vec![t.clone()], None).unwrap();
+        p.connect(&block);
+
+        assert!(p.update(t, c.utxos()).is_err());
+        assert!(p.transactions().is_empty());
+    }
+
+    // ✅ Test: Vested coins can only be pooled once the next block may spend them
+    #[test]
+    fn timelock() {
+        let (mut c, mut wallets) = funded(1);
+        let mut p = Pool::new();
+        let mut w2 = Wallet::new();
+
+        let vesting =
+            Output::new(&w2.public, Amount::from_coins(10)).with_timelock(Timelock::Blocks(2));
+        let t = wallets[0]
+            .send_output(vesting, Amount::ZERO, &c, &mut p)
+            .unwrap();
+        let block = c.add(&Wallet::new().public, vec![t], None).unwrap();
+        p.connect(&block);
+
+        // ❌ The next block is only one after the payment
+        assert!(send(&mut w2, 0, &c, &mut p).is_err());
+        assert!(p.transactions().is_empty());
+
+        c.add(&Wallet::new().public, Vec::new(), None).unwrap();
+        send(&mut w2, 0, &c, &mut p).unwrap();
+        assert_eq!(p.select(c.utxos(), MAX_BLOCK_SIZE).len(), 1);
+    }
+
+    // ✅ Test: A block takes its own transactions and any conflicting ones out of the pool
+    #[test]
+    fn connect() {
+        let (mut c, mut wallets) = funded(3);
+        let mut p = Pool::new();
+
+        let t = send(&mut wallets[0], 1, &c, &mut p).unwrap();
+        send(&mut wallets[1], 1, &c, &mut p).unwrap();
+        send(&mut wallets[2], 1, &c, &mut p).unwrap();
+
+        // A different spend of the coins pooled for wallets[1]
+        let w = &wallets[1];
+        let coins = c.utxos().owned_by(&w.public);
+        let double = Transaction::new(
+            w,
+            &coins,
+            &Wallet::new().public,
+            Amount::from_coins(5),
+            Amount::ZERO,
+            DEFAULT_CHAIN_ID,
+        )
+        .unwrap();
+
+        let block = c.add(&Wallet::new().public, vec![t, double], None).unwrap();
+        p.connect(&block);
+
+        assert_eq!(p.transactions().len(), 1);
+        assert!(p.check(&wallets[2].public).is_some());
+    }
+
+    // ✅ Test: Transactions of orphaned blocks come back unless the new branch has them
+    #[test]
+    fn reorg() {
+        let (mut c, mut wallets) = funded(1);
+        let mut c2 = Chain::new();
+        c2.replace(c.chain.clone()).unwrap();
+        let mut p = Pool::new();
+
+        send(&mut wallets[0], 1, &c, &mut p).unwrap();
+        let block = c
+            .add(
+                &Wallet::new().public,
+                p.select(c.utxos(), MAX_BLOCK_SIZE),
+                None,
+            )
+            .unwrap();
+        p.connect(&block);
+        assert!(p.transactions().is_empty());
+
+        c2.add(&Wallet::new().public, Vec::new(), None).unwrap();
+        c2.add(&Wallet::new().public, Vec::new(), None).unwrap();
+
+        let r = c.reorg(c2.chain.clone()).unwrap();
+        p.reorg(&r, c.utxos());
+
+        assert_eq!(p.transactions(), vec![&block.transactions[1]]);
     }
 }
@@ -0,0 +1,555 @@
+use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::hash::digest;
+
+use super::{
+    output::{Timelock, MAX_MULTISIG_KEYS},
+    transaction::LOCKTIME_THRESHOLD,
+};
+
+/// Most operations a single script may hold.
+pub const MAX_SCRIPT_OPS: usize = 201;
+/// Most bytes a single pushed value may hold.
+pub const MAX_ELEMENT_SIZE: usize = 520;
+/// Most values the stack may hold at once.
+pub const MAX_STACK_SIZE: usize = 100;
+
+/// One step of a script. There are no loops or jumps, so a script runs at
+/// most once through each of its operations.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Op {
+    /// Pushes raw bytes, such as a key, a signature or a hash.
+    Push(#[serde(with = "hex_bytes")] Vec<u8>),
+    /// Pushes a number, as minimal little-endian bytes. Operations taking a
+    /// number fail on any other encoding of it.
+    Num(u64),
+    Dup,
+    Drop,
+    Swap,
+    /// Replaces the top value with its SHA-256 digest.
+    Sha256,
+    Equal,
+    EqualVerify,
+    /// Pops the top value and fails unless it is true.
+    Verify,
+    /// Pops a key, then a DER signature, and pushes whether the signature
+    /// over the sighash is valid for the key.
+    CheckSig,
+    CheckSigVerify,
+    /// Pops a number of keys, the keys, a threshold and that many
+    /// signatures, and pushes whether each signature is valid for one of
+    /// the keys, in the same order.
+    CheckMultisig,
+    /// Pops a height, or a Unix time from `LOCKTIME_THRESHOLD` on, and
+    /// fails unless the `lock_time` of the transaction is of the same kind
+    /// and at least as late.
+    CheckLockTimeVerify,
+    /// Pops a number of blocks and fails unless the input waits at least
+    /// that many blocks after the coin it spends was created.
+    CheckSequenceVerify,
+    /// Pops the top value, which must be the number 0 or 1, and runs up to
+    /// the matching `Else` or `EndIf` only if it is 1.
+    If,
+    NotIf,
+    Else,
+    EndIf,
+}
+
+impl Op {
+    /// Pushes `bytes` the one way an unlocking script may: as a `Num` if
+    /// they are a minimally encoded number, and as a `Push` otherwise.
+    pub fn push(bytes: Vec<u8>) -> Op {
+        match decode_num(&bytes) {
+            Ok(n) => Op::Num(n),
+            Err(_) => Op::Push(bytes),
+        }
+    }
+}
+
+/// What a script may check about the input that runs it.
+pub struct Spend<'a> {
+    /// `sighash` of the spending transaction.
+    pub message: &'a Message,
+    pub lock_time: u64,
+    /// Timelock the input waits for on top of the coin's own.
+    pub timelock: Timelock,
+}
+
+/// A locking script, run after the unlocking script of the input spending
+/// it. The spend is valid if neither fails and they leave a single true
+/// value on the stack.
+///
+/// Unlocking scripts are part of the txid, so each may only push its values
+/// in one way, see `Op::push`, and may not push values the lock leaves
+/// unused. Anyone relaying a spend could otherwise change its txid.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Script(pub Vec<Op>);
+
+impl Script {
+    /// Spendable by a signature of the key whose SHA-256 digest is
+    /// `key_hash`, once the key is revealed.
+    pub fn pay_to_key_hash(key_hash: [u8; 32]) -> Self {
+        Script(vec![
+            Op::Dup,
+            Op::Sha256,
+            Op::Push(key_hash.to_vec()),
+            Op::EqualVerify,
+            Op::CheckSig,
+        ])
+    }
+
+    /// Unlocks `pay_to_key_hash` of `key`.
+    pub fn unlock_key(signature: &Signature, key: &PublicKey) -> Self {
+        Script(vec![
+            Op::push(signature.serialize_der().to_vec()),
+            Op::push(key.serialize().to_vec()),
+        ])
+    }
+
+    /// Checks the script against the size limits and that every `If` is
+    /// closed.
+    pub fn check(&self) -> Result<(), String> {
+        if self.0.len() > MAX_SCRIPT_OPS {
+            return Err(format!(
+                "script holds more than {} operations.",
+                MAX_SCRIPT_OPS
+            ));
+        }
+
+        let mut depth: usize = 0;
+        for op in self.0.iter() {
+            match op {
+                Op::Push(bytes) if bytes.len() > MAX_ELEMENT_SIZE => {
+                    return Err(format!(
+                        "script pushes more than {} bytes.",
+                        MAX_ELEMENT_SIZE
+                    ));
+                }
+                Op::If | Op::NotIf => depth += 1,
+                Op::Else if depth == 0 => return Err("script has an unopened Else.".to_string()),
+                Op::EndIf => {
+                    depth = depth
+                        .checked_sub(1)
+                        .ok_or("script has an unopened EndIf.")?
+                }
+                _ => {}
+            }
+        }
+
+        match depth {
+            0 => Ok(()),
+            _ => Err("script has an unclosed If.".to_string()),
+        }
+    }
+
+    /// Runs `unlock`, which may only push values, and then this script on
+    /// the stack it leaves.
+    pub fn run(&self, unlock: &Script, spend: &Spend) -> Result<(), String> {
+        self.check()?;
+        unlock.check()?;
+
+        if !unlock
+            .0
+            .iter()
+            .all(|op| matches!(op, Op::Push(_) | Op::Num(_)))
+        {
+            return Err("unlocking script may only push values.".to_string());
+        }
+        if unlock
+            .0
+            .iter()
+            .any(|op| matches!(op, Op::Push(bytes) if decode_num(bytes).is_ok()))
+        {
+            return Err("unlocking script pushes a number as bytes.".to_string());
+        }
+
+        let mut stack = Vec::new();
+        unlock.execute(&mut stack, spend)?;
+        self.execute(&mut stack, spend)?;
+
+        match stack.as_slice() {
+            [top] if is_true(top) => Ok(()),
+            _ => Err("script did not end with a single true value.".to_string()),
+        }
+    }
+
+    fn execute(&self, stack: &mut Vec<Vec<u8>>, spend: &Spend) -> Result<(), String> {
+        // Whether each open `If` is on the branch being run
+        let mut branches: Vec<bool> = Vec::new();
+
+        for op in self.0.iter() {
+            let running = branches.iter().all(|b| *b);
+
+            match op {
+                Op::If | Op::NotIf => {
+                    let taken = running && (decode_bool(&pop(stack)?)? == (*op == Op::If));
+                    branches.push(taken);
+                }
+                Op::Else => {
+                    let branch = branches.last_mut().ok_or("unopened Else.")?;
+                    *branch = !*branch;
+                }
+                Op::EndIf => {
+                    branches.pop().ok_or("unopened EndIf.")?;
+                }
+                _ if !running => {}
+                Op::Push(bytes) => stack.push(bytes.clone()),
+                Op::Num(n) => stack.push(encode_num(*n)),
+                Op::Dup => {
+                    let top = stack.last().ok_or("stack is empty.")?.clone();
+                    stack.push(top);
+                }
+                Op::Drop => {
+                    pop(stack)?;
+                }
+                Op::Swap => {
+                    let (a, b) = (pop(stack)?, pop(stack)?);
+                    stack.push(a);
+                    stack.push(b);
+                }
+                Op::Sha256 => {
+                    let top = pop(stack)?;
+                    stack.push(digest(top).to_vec());
+                }
+                Op::Equal => {
+                    let equal = pop(stack)? == pop(stack)?;
+                    stack.push(encode_num(equal as u64));
+                }
+                Op::EqualVerify => {
+                    if pop(stack)? != pop(stack)? {
+                        return Err("values are not equal.".to_string());
+                    }
+                }
+                Op::Verify => {
+                    if !is_true(&pop(stack)?) {
+                        return Err("value is not true.".to_string());
+                    }
+                }
+                Op::CheckSig => {
+                    let (key, signature) = (pop(stack)?, pop(stack)?);
+                    let valid = check_sig(&signature, &key, spend.message);
+                    stack.push(encode_num(valid as u64));
+                }
+                Op::CheckSigVerify => {
+                    let (key, signature) = (pop(stack)?, pop(stack)?);
+                    if !check_sig(&signature, &key, spend.message) {
+                        return Err("signature is not valid.".to_string());
+                    }
+                }
+                Op::CheckMultisig => {
+                    let valid = check_multisig(stack, spend.message)?;
+                    stack.push(encode_num(valid as u64));
+                }
+                Op::CheckLockTimeVerify => {
+                    let n = decode_num(&pop(stack)?)?;
+                    let same_kind =
+                        (n < LOCKTIME_THRESHOLD) == (spend.lock_time < LOCKTIME_THRESHOLD);
+
+                    if !same_kind || spend.lock_time < n {
+                        return Err("lock time is too early.".to_string());
+                    }
+                }
+                Op::CheckSequenceVerify => {
+                    let n = decode_num(&pop(stack)?)?;
+
+                    if !matches!(spend.timelock, Timelock::Blocks(m) if m as u64 >= n) {
+                        return Err("input does not wait long enough.".to_string());
+                    }
+                }
+            }
+
+            if stack.len() > MAX_STACK_SIZE {
+                return Err(format!("stack holds more than {} values.", MAX_STACK_SIZE));
+            }
+        }
+
+        match branches.is_empty() {
+            true => Ok(()),
+            false => Err("unclosed If.".to_string()),
+        }
+    }
+}
+
+fn pop(stack: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, String> {
+    stack.pop().ok_or_else(|| "stack is empty.".to_string())
+}
+
+/// Any value with a non-zero byte is true.
+fn is_true(value: &[u8]) -> bool {
+    value.iter().any(|b| *b != 0)
+}
+
+fn decode_bool(value: &[u8]) -> Result<bool, String> {
+    match decode_num(value)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err("branch condition is neither 0 nor 1.".to_string()),
+    }
+}
+
+fn encode_num(n: u64) -> Vec<u8> {
+    let bytes = n.to_le_bytes();
+    let len = 8 - n.leading_zeros() as usize / 8;
+    bytes[..len].to_vec()
+}
+
+fn decode_num(value: &[u8]) -> Result<u64, String> {
+    if value.len() > 8 {
+        return Err("number is longer than 8 bytes.".to_string());
+    }
+    if value.last() == Some(&0) {
+        return Err("number is not minimally encoded.".to_string());
+    }
+
+    let mut bytes = [0; 8];
+    bytes[..value.len()].copy_from_slice(value);
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn check_sig(signature: &[u8], key: &[u8], message: &Message) -> bool {
+    match (Signature::from_der(signature), PublicKey::from_slice(key)) {
+        (Ok(signature), Ok(key)) => Secp256k1::new()
+            .verify_ecdsa(message, &signature, &key)
+            .is_ok(),
+        _ => false,
+    }
+}
+
+fn check_multisig(stack: &mut Vec<Vec<u8>>, message: &Message) -> Result<bool, String> {
+    let n = decode_num(&pop(stack)?)? as usize;
+    if n > MAX_MULTISIG_KEYS {
+        return Err(format!("more than {} keys.", MAX_MULTISIG_KEYS));
+    }
+    let mut keys = (0..n).map(|_| pop(stack)).collect::<Result<Vec<_>, _>>()?;
+
+    let threshold = decode_num(&pop(stack)?)? as usize;
+    if threshold > n {
+        return Err("threshold is greater than the number of keys.".to_string());
+    }
+    let mut signatures = (0..threshold)
+        .map(|_| pop(stack))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    keys.reverse();
+    signatures.reverse();
+
+    // Each signature must match a later key than the one before it
+    let mut keys = keys.iter();
+    Ok(signatures
+        .iter()
+        .all(|signature| keys.any(|key| check_sig(signature, key, message))))
+}
+
+/// Pushed bytes as a hex string, like keys and signatures.
+mod hex_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        hex::decode(s).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{transaction::wallet::Wallet, utils::hash::Hash};
+
+    /// Lock time and input timelock of a spend that checks neither
+    const ANY: (u64, Timelock) = (0, Timelock::None);
+
+    /// Signs the same message with every wallet, and runs scripts spending with it.
+    struct Vectors {
+        wallets: Vec<Wallet>,
+        hash: Hash,
+    }
+
+    impl Vectors {
+        fn new() -> Self {
+            Vectors {
+                wallets: (0..3).map(|_| Wallet::new()).collect(),
+                hash: Hash::new("vectors").unwrap(),
+            }
+        }
+
+        fn sig(&self, i: usize) -> Op {
+            let signature = self.wallets[i].sign(self.hash.clone());
+            Op::Push(signature.serialize_der().to_vec())
+        }
+
+        fn key(&self, i: usize) -> Op {
+            Op::Push(self.wallets[i].public.serialize().to_vec())
+        }
+
+        fn run(
+            &self,
+            unlock: Vec<Op>,
+            lock: &[Op],
+            (lock_time, timelock): (u64, Timelock),
+        ) -> bool {
+            let message = Message::from_digest(self.hash.to_bytes().unwrap());
+            let spend = Spend {
+                message: &message,
+                lock_time,
+                timelock,
+            };
+            Script(lock.to_vec()).run(&Script(unlock), &spend).is_ok()
+        }
+    }
+
+    #[test]
+    fn key_hash() {
+        let v = Vectors::new();
+        let lock = Script::pay_to_key_hash(digest(v.wallets[0].public.serialize())).0;
+
+        assert!(v.run(vec![v.sig(0), v.key(0)], &lock, ANY));
+        // ❌ Another key, or a signature by another key
+        assert!(!v.run(vec![v.sig(1), v.key(1)], &lock, ANY));
+        assert!(!v.run(vec![v.sig(1), v.key(0)], &lock, ANY));
+        assert!(!v.run(vec![v.key(0)], &lock, ANY));
+    }
+
+    #[test]
+    fn hash_lock() {
+        let v = Vectors::new();
+        let lock = [Op::Sha256, Op::Push(digest("secret").to_vec()), Op::Equal];
+
+        assert!(v.run(vec![Op::push(b"secret".to_vec())], &lock, ANY));
+        assert!(!v.run(vec![Op::push(b"guess".to_vec())], &lock, ANY));
+    }
+
+    // ✅ Test: Scripts check the lock time of the transaction and the timelock of the input
+    #[test]
+    fn timelocks() {
+        let v = Vectors::new();
+        let after_height = [
+            Op::Num(100),
+            Op::CheckLockTimeVerify,
+            v.key(0),
+            Op::CheckSig,
+        ];
+        let after_blocks = [Op::Num(10), Op::CheckSequenceVerify, v.key(0), Op::CheckSig];
+
+        assert!(v.run(vec![v.sig(0)], &after_height, (100, Timelock::None)));
+        assert!(!v.run(vec![v.sig(0)], &after_height, (99, Timelock::None)));
+        // ❌ A time is not a height, however large
+        assert!(!v.run(
+            vec![v.sig(0)],
+            &after_height,
+            (LOCKTIME_THRESHOLD, Timelock::None)
+        ));
+
+        assert!(v.run(vec![v.sig(0)], &after_blocks, (0, Timelock::Blocks(10))));
+        assert!(!v.run(vec![v.sig(0)], &after_blocks, (0, Timelock::Blocks(9))));
+        assert!(!v.run(vec![v.sig(0)], &after_blocks, (0, Timelock::Seconds(3_600))));
+
+        // ❌ A number padded with zeros, though it has the same value
+        let unlock_height = [Op::CheckLockTimeVerify, Op::Num(1)];
+        assert!(v.run(vec![Op::Num(100)], &unlock_height, (100, Timelock::None)));
+        assert!(!v.run(
+            vec![Op::Push(vec![100, 0])],
+            &unlock_height,
+            (100, Timelock::None)
+        ));
+    }
+
+    #[test]
+    fn multisig() {
+        let v = Vectors::new();
+        let lock = [
+            Op::Num(2),
+            v.key(0),
+            v.key(1),
+            v.key(2),
+            Op::Num(3),
+            Op::CheckMultisig,
+        ];
+
+        assert!(v.run(vec![v.sig(0), v.sig(2)], &lock, ANY));
+        assert!(v.run(vec![v.sig(1), v.sig(2)], &lock, ANY));
+        // ❌ Out of order, the same signature twice, or one short
+        assert!(!v.run(vec![v.sig(2), v.sig(0)], &lock, ANY));
+        assert!(!v.run(vec![v.sig(0), v.sig(0)], &lock, ANY));
+        assert!(!v.run(vec![v.sig(0)], &lock, ANY));
+    }
+
+    #[test]
+    fn branches() {
+        let v = Vectors::new();
+        let lock = [
+            Op::If,
+            v.key(0),
+            Op::Else,
+            v.key(1),
+            Op::EndIf,
+            Op::CheckSig,
+        ];
+
+        assert!(v.run(vec![v.sig(0), Op::Num(1)], &lock, ANY));
+        assert!(v.run(vec![v.sig(1), Op::Num(0)], &lock, ANY));
+        assert!(!v.run(vec![v.sig(0), Op::Num(0)], &lock, ANY));
+        // ❌ Conditions other than 0 and 1, though true
+        assert!(!v.run(vec![v.sig(0), Op::Num(2)], &lock, ANY));
+
+        let nested = [
+            Op::NotIf,
+            Op::If,
+            Op::Num(1),
+            Op::EndIf,
+            Op::Else,
+            Op::Num(2),
+            Op::EndIf,
+        ];
+        assert!(v.run(vec![Op::Num(1), Op::Num(0)], &nested, ANY));
+        assert!(v.run(vec![Op::Num(1)], &nested, ANY));
+        assert!(!v.run(vec![Op::Num(0), Op::Num(0)], &nested, ANY));
+
+        // ❌ Unbalanced
+        assert!(!v.run(vec![Op::Num(1)], &[Op::If, Op::Num(1)], ANY));
+        assert!(!v.run(vec![], &[Op::Num(1), Op::Else], ANY));
+        assert!(!v.run(vec![], &[Op::Num(1), Op::EndIf], ANY));
+    }
+
+    // ✅ Test: Scripts that run too long, grow too large or leave no true value fail
+    #[test]
+    fn limits() {
+        let v = Vectors::new();
+
+        assert!(v.run(vec![Op::Num(7), Op::Num(7)], &[Op::Equal], ANY));
+        // ❌ The unlocking script may only push values, each in one way
+        assert!(!v.run(vec![Op::Num(7), Op::Dup], &[Op::Equal], ANY));
+        assert!(!v.run(vec![Op::Num(7), Op::Push(vec![7])], &[Op::Equal], ANY));
+        assert!(!v.run(
+            vec![Op::Push(vec![])],
+            &[Op::NotIf, Op::Num(1), Op::EndIf],
+            ANY
+        ));
+        // ❌ Nothing, something false, or more than the one true value is left
+        assert!(!v.run(vec![], &[Op::Num(0)], ANY));
+        assert!(!v.run(vec![], &[], ANY));
+        assert!(!v.run(vec![Op::Num(5), Op::Num(1)], &[], ANY));
+
+        let fill = |n| [vec![Op::Num(1); n], vec![Op::Drop; n - 1]].concat();
+        assert!(v.run(vec![], &fill(MAX_STACK_SIZE), ANY));
+        assert!(!v.run(vec![], &fill(MAX_STACK_SIZE + 1), ANY));
+        assert!(!v.run(vec![Op::Num(1)], &vec![Op::Dup; MAX_SCRIPT_OPS + 1], ANY));
+
+        let push = |len| vec![Op::Push(vec![1; len])];
+        assert!(v.run(push(MAX_ELEMENT_SIZE), &[], ANY));
+        assert!(!v.run(push(MAX_ELEMENT_SIZE + 1), &[], ANY));
+    }
+
+    #[test]
+    fn json() {
+        let script = Script(vec![Op::Push(vec![0xab, 0x01]), Op::Num(2), Op::CheckSig]);
+        let json = serde_json::to_string(&script).unwrap();
+
+        assert_eq!(json, r#"[{"Push":"ab01"},{"Num":2},"CheckSig"]"#);
+        assert_eq!(serde_json::from_str::<Script>(&json).unwrap(), script);
+    }
+}
@@ -1,105 +1,500 @@
-use std::str::FromStr;
-
-use super::input::Input;
-use super::output::Output;
+use super::amount::Amount;
+use super::input::{Input, OutPoint};
+use super::output::{Lock, Output, Timelock};
+use super::script::Script;
 use super::wallet::Wallet;
 use bincode::serialize;
 use hex::decode;
-use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1};
+use secp256k1::{Message, PublicKey};
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
 
-use crate::utils::hash::Hash;
+use crate::utils::hash::{digest, Hash};
 
-/*const TOTAL_SUPPLY_CAP: f64 = 1_048_576.0; // 2^20
-const INITIAL_REWARD: f64 = 50.0;
-const HALVING_INTERVAL: u64 = 100; // Adjust for your chain
+pub const TOTAL_SUPPLY_CAP: Amount = Amount::from_coins(1_048_576); // 2^20
+pub const INITIAL_REWARD: Amount = Amount::from_coins(50);
+pub const HALVING_INTERVAL: usize = 100; // Adjust for your chain
 
 /// 🔹 Calculate mining reward based on block height
-fn mining_reward(block_height: u64) -> f64 {
-    let halvings = block_height / HALVING_INTERVAL;
-    let reward = INITIAL_REWARD / 2.0_f64.powi(halvings as i32);
-    reward.max(0.0) // Ensure it doesn't go negative
-}*/
+///
+/// This is what the coinbase of the block at `height` may pay on top of the
+/// fees of its block. It halves every `HALVING_INTERVAL` blocks and stops
+/// once `TOTAL_SUPPLY_CAP` coins have been issued.
+pub fn mining_reward(height: usize) -> Amount {
+    match height {
+        0 => Amount::ZERO,
+        _ => issued(height).checked_sub(issued(height - 1)).unwrap(),
+    }
+}
+
+/// Coins issued by the blocks up to and including `height`.
+pub fn issued(height: usize) -> Amount {
+    let mut total: u128 = 0;
+
+    // The genesis block pays nothing, each era after it half the one before.
+    for halvings in 0..u64::BITS as usize {
+        let start = (halvings * HALVING_INTERVAL).max(1);
+        let end = ((halvings + 1) * HALVING_INTERVAL - 1).min(height);
+
+        if start > height {
+            break;
+        }
+        if end >= start {
+            let reward = INITIAL_REWARD.units() >> halvings;
+            total += (end - start + 1) as u128 * reward as u128;
+        }
+    }
+
+    Amount::from_units(total.min(TOTAL_SUPPLY_CAP.units() as u128) as u64)
+}
+
+/// First height after `height` at which the reward halves.
+pub fn next_halving(height: usize) -> usize {
+    (height / HALVING_INTERVAL + 1) * HALVING_INTERVAL
+}
 
-const REWARD: f64 = 50.;
+/// Prefix of every signed message, so a transaction signature can never be
+/// passed off as a signature over anything else.
+pub const SIGHASH_TAG: &[u8] = b"crate/transaction/sighash";
+/// Version of the layout of the signed message.
+pub const SIGHASH_VERSION: u32 = 1;
+/// Network of a node started without `CHAIN_ID` set. Transactions are
+/// signed for one network, and every other network rejects them.
+pub const DEFAULT_CHAIN_ID: u32 = 1;
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+/// Lock times below this are block heights, the rest Unix timestamps.
+pub const LOCKTIME_THRESHOLD: u64 = 500_000_000;
+
+/// A transfer of coins, or a coinbase if it has no inputs.
+///
+/// Each input spends one coin and is signed by its owner, so the coins of
+/// several wallets can be combined in one transaction.
+///
+/// It is identified by its `txid`, the hash of its contents, and the coins
+/// it spends can only be spent once, so a transaction cannot be replayed: a
+/// copy has the same txid and spends coins that are already gone.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct Transaction {
-    pub id: Uuid,
+    pub inputs: Vec<Input>,
     pub outputs: Vec<Output>,
-    pub input: Option<Input>,
+    /// Height of the block a coinbase belongs to, so that no two coinbases
+    /// are alike. `None` for every other transaction.
+    pub height: Option<usize>,
+    /// First block height, or Unix timestamp if at least
+    /// `LOCKTIME_THRESHOLD`, at which the transaction may be mined. `0` if
+    /// it may be mined right away.
+    #[serde(default)]
+    pub lock_time: u64,
 }
 
 impl Transaction {
-    pub fn new(sender: &Wallet, receiver: &PublicKey, amount: f64) -> Result<Transaction, String> {
-        if amount > sender.balance {
-            return Err("amount greater than balance.".to_string());
-        }
-
-        let mut outputs = Vec::new();
-        outputs.push(Output::new(&sender.public, sender.balance - amount));
-        outputs.push(Output::new(receiver, amount));
+    /// Spends all of `coins`, which must belong to `sender`, paying `amount`
+    /// to `receiver`, `fee` to the miner and the rest back to `sender`.
+    pub fn new(
+        sender: &Wallet,
+        coins: &[(OutPoint, Output)],
+        receiver: impl Into<Lock>,
+        amount: Amount,
+        fee: Amount,
+        chain_id: u32,
+    ) -> Result<Transaction, String> {
+        let payment = Output::new(receiver, amount);
+        let mut t = Transaction::pay(coins, payment, fee, &sender.public)?;
+        t.sign(sender, chain_id)?;
 
-        let t = Transaction {
-            id: Uuid::new_v4(),
-            outputs: outputs.clone(),
-            input: Some(Input::new(&sender, &outputs)),
-        };
-
-        match t.verify() {
+        match t.verify(chain_id) {
             Ok(_) => Ok(t),
             Err(_) => Err("signature does not match.".to_string()),
         }
     }
 
-    pub fn reward(miner: &PublicKey) -> Transaction {
-        let mut outputs = Vec::new();
-        outputs.push(Output::new(miner, REWARD));
+    /// Unsigned transaction spending all of `coins` into `payment`, paying
+    /// `fee` to the miner and the rest to `change`.
+    pub fn pay(
+        coins: &[(OutPoint, Output)],
+        payment: Output,
+        fee: Amount,
+        change: impl Into<Lock>,
+    ) -> Result<Transaction, String> {
+        let total = Amount::sum(coins.iter().map(|(_, o)| o.amount)).ok_or("inputs overflow.")?;
+        let rest = payment
+            .amount
+            .checked_add(fee)
+            .and_then(|spent| total.checked_sub(spent))
+            .ok_or("amount greater than balance.")?;
+
+        let outputs = vec![Output::new(change, rest), payment];
 
+        Ok(Transaction::build(coins, outputs))
+    }
+
+    /// Unsigned transaction spending `coins` into `outputs`. The coins may
+    /// belong to several wallets, each of which then has to `sign` it.
+    pub fn build(coins: &[(OutPoint, Output)], outputs: Vec<Output>) -> Transaction {
         Transaction {
-            id: Uuid::new_v4(),
-            outputs: outputs.clone(),
-            input: None,
+            inputs: coins
+                .iter()
+                .map(|(p, o)| Input::new(p.clone(), o.lock.clone()))
+                .collect(),
+            outputs,
+            height: None,
+            lock_time: 0,
         }
     }
 
-    pub fn update(&mut self, sender: &Wallet, receiver: &PublicKey, amount: f64) {
-        let x = self.clone();
+    /// Coinbase of the block at `height` paying `amount` to `miner`.
+    pub fn reward(miner: &PublicKey, amount: Amount, height: usize) -> Transaction {
+        Transaction {
+            inputs: Vec::new(),
+            outputs: vec![Output::new(miner, amount)],
+            height: Some(height),
+            lock_time: 0,
+        }
+    }
 
-        match self.outputs.iter_mut().find(|n| n.address == sender.public) {
-            Some(sender_output) => {
-                if amount > sender_output.amount {
-                    return;
-                }
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.is_empty()
+    }
 
-                sender_output.amount -= amount;
-                self.outputs.push(Output::new(receiver, amount));
-                self.input = Some(Input::new(&sender, &self.outputs))
+    /// Whether `lock_time` has passed for a block at `height` with timestamp `time`.
+    pub fn is_final(&self, height: usize, time: u64) -> bool {
+        match self.lock_time {
+            0 => true,
+            t if t < LOCKTIME_THRESHOLD => height as u64 >= t,
+            t => time >= t,
+        }
+    }
+
+    /// Adds the signature of `wallet` to every input whose lock lists its
+    /// key or pays to the hash of its key, for the network `chain_id`. A
+    /// signature already in its slot is renewed, but no new one is added to
+    /// an input that has as many as its lock's threshold.
+    pub fn sign(&mut self, wallet: &Wallet, chain_id: u32) -> Result<(), String> {
+        let hash = self.sighash(chain_id);
+        let key_hash = Lock::Script(Script::pay_to_key_hash(digest(wallet.public.serialize())));
+        let mut signed = false;
+
+        for input in self.inputs.iter_mut() {
+            if input.lock == key_hash {
+                input.script = Script::unlock_key(&wallet.sign(hash.clone()), &wallet.public);
+                signed = true;
             }
-            None => (),
-        };
+            let mut filled = input.signatures.iter().flatten().count();
+            for (key, signature) in input.lock.keys().iter().zip(input.signatures.iter_mut()) {
+                if *key != wallet.public {
+                    continue;
+                }
+                if signature.is_some() || filled < input.lock.threshold() {
+                    filled += signature.is_none() as usize;
+                    *signature = Some(wallet.sign(hash.clone()));
+                }
+                signed = true;
+            }
+        }
 
-        match self.verify() {
-            Ok(_) => return,
-            Err(_) => {
-                *self = x;
+        match signed {
+            true => Ok(()),
+            false => Err("no input belongs to this wallet.".to_string()),
+        }
+    }
+
+    /// Adds the signatures and unlocking scripts of `other`, a copy of this
+    /// transaction signed by other keys, until each input has as many
+    /// signatures as its lock's threshold. Every signature of `other` must be
+    /// valid, so that no slot is filled with one that is not.
+    pub fn combine(&mut self, other: &Transaction, chain_id: u32) -> Result<(), String> {
+        let hash = self.sighash(chain_id);
+        if hash != other.sighash(chain_id) {
+            return Err("transactions differ in more than their signatures.".to_string());
+        }
+
+        let message = Message::from_digest(hash.to_bytes().unwrap());
+        if other
+            .inputs
+            .iter()
+            .any(|i| i.lock.count_signatures(&message, &i.signatures).is_none())
+        {
+            return Err("signature does not match.".to_string());
+        }
+
+        for (input, theirs) in self.inputs.iter_mut().zip(other.inputs.iter()) {
+            if input.script.0.is_empty() {
+                input.script = theirs.script.clone();
+            }
+            let mut filled = input.signatures.iter().flatten().count();
+            for (signature, their) in input.signatures.iter_mut().zip(theirs.signatures.iter()) {
+                if signature.is_none() && their.is_some() && filled < input.lock.threshold() {
+                    *signature = *their;
+                    filled += 1;
+                }
             }
         }
+
+        Ok(())
     }
 
-    pub fn verify(&self) -> Result<(), ()> {
-        let s = Secp256k1::new();
+    /// Adds `payment`, taking its amount and an extra `fee` out of the
+    /// sender's change. Every input must belong to `sender`, since changing
+    /// the outputs takes new signatures.
+    pub fn update(
+        &mut self,
+        sender: &Wallet,
+        payment: Output,
+        fee: Amount,
+        chain_id: u32,
+    ) -> Result<(), String> {
+        let own = Lock::Key(sender.public);
+
+        if self.is_coinbase() || self.inputs.iter().any(|i| i.lock != own) {
+            return Err("transaction has inputs of other senders.".to_string());
+        }
+
+        let x = self.clone();
+
+        let sender_output = self
+            .outputs
+            .iter_mut()
+            .find(|n| n.lock == own)
+            .ok_or("transaction pays no change to the sender.")?;
+        let change = payment
+            .amount
+            .checked_add(fee)
+            .and_then(|spent| sender_output.amount.checked_sub(spent))
+            .ok_or("amount greater than change.")?;
+
+        sender_output.amount = change;
+        self.outputs.push(payment);
+        self.sign(sender, chain_id)?;
+
+        if self.verify(chain_id).is_err() {
+            *self = x;
+            return Err("signature does not match.".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// What every input signature commits to: the hash of `preimage`.
+    pub fn sighash(&self, chain_id: u32) -> Hash {
+        Hash::new(self.preimage(chain_id)).unwrap()
+    }
+
+    /// `SIGHASH_TAG`, then `SIGHASH_VERSION` and `chain_id` as little-endian
+    /// `u32`s, then the bincode encoding of the outpoint, lock and timelock
+    /// of every input, of the outputs and of `lock_time`. Unlocking scripts
+    /// are left out, since they carry the signatures.
+    ///
+    /// Spent amounts are covered through the outpoints, since each names
+    /// the txid of the transaction holding the output.
+    pub fn preimage(&self, chain_id: u32) -> Vec<u8> {
+        let inputs: Vec<(&OutPoint, &Lock, &Timelock)> = self
+            .inputs
+            .iter()
+            .map(|i| (&i.outpoint, &i.lock, &i.timelock))
+            .collect();
+
+        let mut preimage = SIGHASH_TAG.to_vec();
+        preimage.extend(SIGHASH_VERSION.to_le_bytes());
+        preimage.extend(chain_id.to_le_bytes());
+        preimage.extend(serialize(&(inputs, &self.outputs, self.lock_time)).unwrap());
+        preimage
+    }
+
+    /// Id of the transaction: the hash of its canonical encoding, which is
+    /// bincode with fixed-width little-endian integers. It is used as its
+    /// Merkle tree leaf and to refer to its outputs, and is the same on
+    /// every node.
+    pub fn txid(&self) -> Hash {
+        Hash::new(serialize(self).unwrap()).unwrap()
+    }
+
+    /// Outpoint of each output this transaction creates.
+    pub fn outpoints(&self) -> impl Iterator<Item = (OutPoint, &Output)> {
+        let txid = self.txid();
+        self.outputs.iter().enumerate().map(move |(index, o)| {
+            (
+                OutPoint {
+                    txid: txid.clone(),
+                    index,
+                },
+                o,
+            )
+        })
+    }
+
+    /// Checks that there are inputs and each carries enough valid
+    /// signatures, or a valid unlocking script, for its lock, signed for the
+    /// network `chain_id`.
+    pub fn verify(&self, chain_id: u32) -> Result<(), ()> {
+        if self.is_coinbase() {
+            return Err(());
+        }
+        let hash = self.sighash(chain_id);
+        let message = Message::from_digest(decode(hash.0).unwrap().try_into().unwrap());
+
+        match self
+            .inputs
+            .iter()
+            .all(|i| i.verify(&message, self.lock_time))
+        {
+            true => Ok(()),
+            false => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halving() {
+        let half = Amount::from_units(INITIAL_REWARD.units() / 2);
+
+        assert_eq!(mining_reward(0), Amount::ZERO);
+        assert_eq!(mining_reward(1), INITIAL_REWARD);
+        assert_eq!(mining_reward(HALVING_INTERVAL - 1), INITIAL_REWARD);
+        assert_eq!(mining_reward(HALVING_INTERVAL), half);
+        assert_eq!(mining_reward(HALVING_INTERVAL * 64), Amount::ZERO);
+
+        assert_eq!(next_halving(0), HALVING_INTERVAL);
+        assert_eq!(next_halving(HALVING_INTERVAL), HALVING_INTERVAL * 2);
+    }
+
+    // ✅ Test: Issuance adds up block by block and never passes the cap
+    #[test]
+    fn supply() {
+        let mut total = Amount::ZERO;
+        for height in 0..HALVING_INTERVAL * 3 {
+            total = total.checked_add(mining_reward(height)).unwrap();
+            assert_eq!(issued(height), total);
+        }
+
+        assert!(issued(usize::MAX / 2) <= TOTAL_SUPPLY_CAP);
+        assert_eq!(issued(usize::MAX / 2), issued(HALVING_INTERVAL * 64));
+    }
+
+    // ✅ Test: A signature does not carry over to other inputs with the same outputs
+    #[test]
+    fn lifted_signature() {
+        let w = Wallet::new();
+        let coin = |index| {
+            let txid = Hash::blank();
+            let output = Output::new(&w.public, Amount::from_coins(5));
+            (OutPoint { txid, index }, output)
+        };
+        let pay = |coins: &[(OutPoint, Output)]| {
+            Transaction::new(
+                &w,
+                coins,
+                &w.public,
+                Amount::from_coins(1),
+                Amount::ZERO,
+                DEFAULT_CHAIN_ID,
+            )
+            .unwrap()
+        };
+
+        let t = pay(&[coin(0)]);
+        let mut lifted = pay(&[coin(1)]);
+        assert_eq!(lifted.outputs, t.outputs);
+
+        lifted.inputs[0].signatures = t.inputs[0].signatures.clone();
+        assert!(lifted.verify(DEFAULT_CHAIN_ID).is_err());
+    }
+
+    #[test]
+    fn preimage() {
+        let w = Wallet::new();
+        let outpoint = OutPoint {
+            txid: Hash::blank(),
+            index: 1,
+        };
+        let t = Transaction::build(
+            &[(outpoint.clone(), Output::new(&w.public, Amount::ZERO))],
+            vec![],
+        );
+        let preimage = t.preimage(DEFAULT_CHAIN_ID);
+        let (tag, rest) = preimage.split_at(SIGHASH_TAG.len());
+
+        assert_eq!(tag, SIGHASH_TAG);
+        assert_eq!(rest[..8], [1, 0, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(
+            rest[8..],
+            serialize(&(
+                vec![(&outpoint, &Lock::Key(w.public), &Timelock::None)],
+                Vec::<Output>::new(),
+                0u64
+            ))
+            .unwrap()
+        );
+    }
+
+    // ✅ Test: Coins of several wallets are spent together once each owner signs
+    #[test]
+    fn joint() {
+        let (a, b) = (Wallet::new(), Wallet::new());
+        let coin = |w: &Wallet, index| {
+            let txid = Hash::blank();
+            let output = Output::new(&w.public, Amount::from_coins(5));
+            (OutPoint { txid, index }, output)
+        };
+
+        let receiver = Wallet::new().public;
+        let mut t = Transaction::build(
+            &[coin(&a, 0), coin(&b, 1)],
+            vec![Output::new(&receiver, Amount::from_coins(10))],
+        );
+
+        t.sign(&a, DEFAULT_CHAIN_ID).unwrap();
+        assert!(t.verify(DEFAULT_CHAIN_ID).is_err());
+        t.sign(&b, DEFAULT_CHAIN_ID).unwrap();
+        assert!(t.verify(DEFAULT_CHAIN_ID).is_ok());
+
+        assert!(t.sign(&Wallet::new(), DEFAULT_CHAIN_ID).is_err());
+        assert!(t
+            .clone()
+            .update(
+                &a,
+                Output::new(&receiver, Amount::ZERO),
+                Amount::ZERO,
+                DEFAULT_CHAIN_ID
+            )
+            .is_err());
+    }
+
+    // ✅ Test: Coins paid to the hash of a key are spent by revealing the key
+    #[test]
+    fn key_hash() {
+        let w = Wallet::new();
+        let lock = Lock::Script(Script::pay_to_key_hash(digest(w.public.serialize())));
+        let coin = (
+            OutPoint {
+                txid: Hash::blank(),
+                index: 0,
+            },
+            Output::new(lock, Amount::from_coins(5)),
+        );
+
+        let receiver = Wallet::new().public;
+        let mut t =
+            Transaction::build(&[coin], vec![Output::new(&receiver, Amount::from_coins(5))]);
+        assert!(t.verify(DEFAULT_CHAIN_ID).is_err());
+        assert!(t.sign(&Wallet::new(), DEFAULT_CHAIN_ID).is_err());
+
+        t.sign(&w, DEFAULT_CHAIN_ID).unwrap();
+        assert!(t.verify(DEFAULT_CHAIN_ID).is_ok());
 
-        let hash = Hash::new(serialize(&self.outputs).unwrap()).unwrap();
+        // ❌ The unlocking script only fits the outputs it signed
+        let mut redirected = t.clone();
+        redirected.outputs[0] = Output::new(&w.public, Amount::from_coins(5));
+        assert!(redirected.verify(DEFAULT_CHAIN_ID).is_err());
 
-        match s.verify_ecdsa(
-            &Message::from_digest(decode(hash.0).unwrap().try_into().unwrap()),
-            &self.input.unwrap().signature,
-            &self.input.unwrap().address,
-        ) {
-            Ok(_) => Ok(()),
-            Err(_) => Err(()),
+        // ❌ Signature slots, which a script lock has no use for
+        for slot in [None, Some(w.sign(t.sighash(DEFAULT_CHAIN_ID)))] {
+            let mut padded = t.clone();
+            padded.inputs[0].signatures.push(slot);
+            assert_ne!(padded.txid(), t.txid());
+            assert!(padded.verify(DEFAULT_CHAIN_ID).is_err());
         }
     }
 }
@@ -1,14 +1,34 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+};
+
 use hex::decode;
 use secp256k1::{ecdsa::Signature, rand::rngs::OsRng, Message, PublicKey, Secp256k1, SecretKey};
-use serde_json::from_str;
 
-use crate::{blockchain::chain::Chain, utils::hash::Hash};
+use crate::{
+    blockchain::chain::Chain,
+    utils::{hash::Hash, time},
+};
+
+use super::{
+    amount::Amount,
+    htlc::Htlc,
+    input::OutPoint,
+    output::{Lock, Output},
+    pool::Pool,
+    script::Script,
+    transaction::Transaction,
+};
 
-use super::{pool::Pool, transaction::Transaction};
+/// File in the data directory holding the hex secret key of the node's wallet.
+const KEY_FILE: &str = "wallet.key";
 
 #[derive(Copy, Clone)]
 pub struct Wallet {
-    pub balance: f64,
+    pub balance: Amount,
     secret: SecretKey,
     pub public: PublicKey,
 }
@@ -19,107 +39,247 @@ impl Wallet {
         let (secret, public) = secp.generate_keypair(&mut OsRng);
 
         Wallet {
-            balance: 50.,
+            balance: Amount::ZERO,
             secret,
             public,
         }
     }
 
+    /// Loads the wallet whose key is kept in `dir`, creating one on first
+    /// use, so the node can still spend its coins after a restart.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, String> {
+        let dir = dir.as_ref();
+        let path = dir.join(KEY_FILE);
+
+        if path.exists() {
+            let hex = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+            let bytes = decode(hex.trim()).map_err(|e| e.to_string())?;
+            let secret = SecretKey::from_slice(&bytes).map_err(|e| e.to_string())?;
+
+            return Ok(Wallet {
+                balance: Amount::ZERO,
+                secret,
+                public: secret.public_key(&Secp256k1::new()),
+            });
+        }
+
+        let w = Wallet::new();
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .map_err(|e| e.to_string())?;
+        file.write_all(hex::encode(w.secret.secret_bytes()).as_bytes())
+            .and_then(|_| file.sync_all())
+            .map_err(|e| e.to_string())?;
+
+        Ok(w)
+    }
+
     pub fn sign(&self, hash: Hash) -> Signature {
         let secp = Secp256k1::new();
         let message = Message::from_digest(decode(hash.0).unwrap().try_into().unwrap());
-        return secp.sign_ecdsa(&message, &self.secret);
+        secp.sign_ecdsa(&message, &self.secret)
     }
 
+    /// Pays `amount` to `receiver`, offering `fee` to whoever mines it. If
+    /// the pool already holds a transaction from this wallet, the payment is
+    /// added to it.
     pub fn send(
         &mut self,
-        receiver: &PublicKey,
-        amount: f64,
+        receiver: impl Into<Lock>,
+        amount: Amount,
+        fee: Amount,
+        c: &Chain,
+        tp: &mut Pool,
+    ) -> Result<Transaction, String> {
+        self.send_output(Output::new(receiver, amount), fee, c, tp)
+    }
+
+    /// Like `send`, for a payment that may carry a timelock, e.g. vesting.
+    pub fn send_output(
+        &mut self,
+        payment: Output,
+        fee: Amount,
         c: &Chain,
         tp: &mut Pool,
     ) -> Result<Transaction, String> {
         self.balance = self.calculate_balance(c);
-        if amount > self.balance {
+        if payment
+            .amount
+            .checked_add(fee)
+            .is_none_or(|spent| spent > self.balance)
+        {
             return Err("amount greater than balance.".to_string());
         }
 
-        match tp.check(self.public) {
-            Some(t) => {
-                t.update(&self, receiver, amount);
-                return Ok(t.clone());
+        let own = Lock::Key(self.public);
+        let t = match tp.check(&self.public).cloned() {
+            Some(mut t) if t.inputs.iter().all(|i| i.lock == own) => {
+                t.update(self, payment, fee, c.chain_id())?;
+                t
             }
-            None => {
-                let t = Transaction::new(&self, receiver, amount).unwrap();
-                tp.update(t.clone());
-                return Ok(t);
+            // A joint transaction stays as its senders signed it, so pay
+            // with the coins it leaves alone.
+            _ => {
+                let coins: Vec<_> = spendable(c, &self.public)
+                    .into_iter()
+                    .filter(|(p, _)| !tp.spends(p))
+                    .collect();
+                let mut t = Transaction::pay(&coins, payment, fee, &self.public)?;
+                t.sign(self, c.chain_id())?;
+                t
             }
+        };
+
+        tp.update(t.clone(), c.utxos())?;
+        Ok(t)
+    }
+
+    /// Starts spending the coins locked by `lock`, one of whose keys is this
+    /// wallet's, paying `amount` to `receiver`, `fee` to the miner and the
+    /// rest back to `lock`.
+    ///
+    /// The result carries only this wallet's signature. The other key holders
+    /// add theirs with `Transaction::sign`, and partially signed copies are
+    /// merged with `Transaction::combine`, until `lock.threshold()` is met.
+    pub fn propose(
+        &self,
+        lock: &Lock,
+        receiver: impl Into<Lock>,
+        amount: Amount,
+        fee: Amount,
+        c: &Chain,
+    ) -> Result<Transaction, String> {
+        let coins = spendable(c, lock.clone());
+        if coins.is_empty() {
+            return Err("no coins are locked by this lock.".to_string());
         }
+
+        let payment = Output::new(receiver, amount);
+        let mut t = Transaction::pay(&coins, payment, fee, lock.clone())?;
+        t.sign(self, c.chain_id())?;
+        Ok(t)
     }
 
-    pub fn calculate_balance(&mut self, c: &Chain) -> f64 {
-        let mut transactions: Vec<Transaction> = Vec::new();
+    /// Locks `amount` of this wallet's coins in `htlc`.
+    pub fn create_htlc(
+        &mut self,
+        htlc: &Htlc,
+        amount: Amount,
+        fee: Amount,
+        c: &Chain,
+        tp: &mut Pool,
+    ) -> Result<Transaction, String> {
+        self.send_output(Output::new(htlc.lock()?, amount), fee, c, tp)
+    }
 
-        // 🔹 **Extract Transactions from Blocks**
-        for block in c.chain.iter() {
-            // Try to parse the block data into a vector of transactions
-            if let Ok(block_transactions) = from_str::<Vec<Transaction>>(&block.data) {
-                transactions.extend(block_transactions);
-            }
+    /// Takes the coins held by `htlc`, which pays this wallet, by revealing
+    /// `secret`.
+    pub fn claim_htlc(
+        &self,
+        htlc: &Htlc,
+        secret: &[u8],
+        fee: Amount,
+        c: &Chain,
+        tp: &mut Pool,
+    ) -> Result<Transaction, String> {
+        if htlc.receiver != self.public {
+            return Err("HTLC pays another wallet.".to_string());
+        }
+        if Hash::new(secret)? != htlc.hash {
+            return Err("secret does not match the hash.".to_string());
         }
 
-        // 🔹 **Find Transactions Sent by This Wallet**
-        let wallet_input_txs: Vec<&Transaction> = transactions
-            .iter()
-            .filter(|tx| {
-                if let Some(input) = tx.input {
-                    input.address == self.public
-                } else {
-                    false
-                }
-            })
-            .collect();
-
-        let mut start_time: u64 = 0;
-
-        // 🔹 **Find the Most Recent Transaction Output Affecting This Wallet**
-        if !wallet_input_txs.is_empty() {
-            if let Some(recent_input_tx) = wallet_input_txs
-                .iter()
-                .max_by_key(|tx| tx.input.unwrap().timestamp)
-            {
-                // Find the most recent balance update
-                if let Some(output) = recent_input_tx
-                    .outputs
-                    .iter()
-                    .find(|o| o.address == self.public)
-                {
-                    self.balance = output.amount;
-                    start_time = recent_input_tx.input.unwrap().timestamp;
-                }
-            }
+        self.spend_htlc(htlc, fee, 0, |sig| htlc.claim(sig, secret), c, tp)
+    }
+
+    /// Takes back the coins held by `htlc`, which this wallet funded, once
+    /// its deadline has passed.
+    pub fn refund_htlc(
+        &self,
+        htlc: &Htlc,
+        fee: Amount,
+        c: &Chain,
+        tp: &mut Pool,
+    ) -> Result<Transaction, String> {
+        if htlc.sender != self.public {
+            return Err("HTLC refunds another wallet.".to_string());
         }
 
-        // 🔹 **Apply Transactions After the Last Known Balance Update**
-        for transaction in transactions.iter() {
-            if let Some(input) = transaction.input {
-                if input.timestamp > start_time {
-                    for output in transaction.outputs.iter() {
-                        if output.address == self.public {
-                            self.balance += output.amount;
-                        }
-                    }
-                }
-            }
+        self.spend_htlc(htlc, fee, htlc.deadline, |sig| htlc.refund(sig), c, tp)
+    }
+
+    /// Pools a transaction paying all coins of `htlc` but `fee` to this
+    /// wallet, each input unlocked by `unlock`.
+    fn spend_htlc(
+        &self,
+        htlc: &Htlc,
+        fee: Amount,
+        lock_time: u64,
+        unlock: impl Fn(&Signature) -> Script,
+        c: &Chain,
+        tp: &mut Pool,
+    ) -> Result<Transaction, String> {
+        let coins = c.utxos().owned_by(htlc.lock()?);
+        if coins.is_empty() {
+            return Err("no coins are locked by this HTLC.".to_string());
         }
 
+        let total = Amount::sum(coins.iter().map(|(_, o)| o.amount)).ok_or("inputs overflow.")?;
+        let amount = total
+            .checked_sub(fee)
+            .ok_or("fee greater than the locked coins.")?;
+
+        let mut t = Transaction::build(&coins, vec![Output::new(&self.public, amount)]);
+        t.lock_time = lock_time;
+
+        let signature = self.sign(t.sighash(c.chain_id()));
+        for input in t.inputs.iter_mut() {
+            input.script = unlock(&signature);
+        }
+
+        tp.update(t.clone(), c.utxos())?;
+        Ok(t)
+    }
+
+    /// Sums the unspent outputs paying to this wallet, leaving out those
+    /// still time-locked.
+    pub fn calculate_balance(&mut self, c: &Chain) -> Amount {
+        let coins = spendable(c, &self.public);
+        self.balance = Amount::sum(coins.iter().map(|(_, o)| o.amount)).unwrap_or(Amount::MAX);
+
         self.balance
     }
 }
 
+/// Coins locked by `lock` that the next block may spend, leaving out those
+/// still time-locked.
+fn spendable(c: &Chain, lock: impl Into<Lock>) -> Vec<(OutPoint, Output)> {
+    let utxos = c.utxos();
+    utxos.spendable_by(lock, utxos.height() + 1, time())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serde_json::json;
+    use crate::transaction::{
+        output::Timelock, pool::MAX_BLOCK_SIZE, transaction::DEFAULT_CHAIN_ID,
+    };
+
+    // ✅ Test: The wallet kept in a data directory survives a restart
+    #[test]
+    fn open() {
+        let dir = std::env::temp_dir().join(format!("wallet-{}", uuid::Uuid::new_v4()));
+
+        let w = Wallet::open(&dir).unwrap();
+        assert_eq!(Wallet::open(&dir).unwrap().public, w.public);
+        assert_ne!(Wallet::open(dir.join("other")).unwrap().public, w.public);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
 
     #[test]
     fn balance_check() {
@@ -127,15 +287,23 @@ mod tests {
         let mut w2 = Wallet::new();
         let mut c = Chain::new();
         let mut p = Pool::new();
-        let add = 10.;
-        for i in 0..3 {
-            w.send(&w2.public, add, &c, &mut p);
+        let add = Amount::from_coins(10);
+
+        c.add(&w.public, Vec::new(), None).unwrap();
+
+        for _ in 0..3 {
+            w.send(&w2.public, add, Amount::ZERO, &c, &mut p).unwrap();
         }
 
-        c.add(json!(p.transactions).to_string().as_str());
+        c.add(
+            &Wallet::new().public,
+            p.select(c.utxos(), MAX_BLOCK_SIZE),
+            None,
+        )
+        .unwrap();
 
-        assert_eq!(w2.calculate_balance(&c), 80.);
-        assert_eq!(w.calculate_balance(&c), 20.);
+        assert_eq!(w2.calculate_balance(&c), Amount::from_coins(30));
+        assert_eq!(w.calculate_balance(&c), Amount::from_coins(20));
     }
 
     #[test]
@@ -145,19 +313,235 @@ mod tests {
         let mut c = Chain::new();
         let mut p = Pool::new();
 
+        c.add(&w.public, Vec::new(), None).unwrap();
+
         let b = w.calculate_balance(&c);
 
-        w2.send(&w.public, 60., &c, &mut p);
+        assert!(w2
+            .send(&w.public, Amount::from_coins(60), Amount::ZERO, &c, &mut p)
+            .is_err());
 
-        c.add(json!(p.transactions).to_string().as_str());
+        c.add(
+            &Wallet::new().public,
+            p.select(c.utxos(), MAX_BLOCK_SIZE),
+            None,
+        )
+        .unwrap();
         p.clear();
 
-        w.send(&w2.public, 10., &c, &mut p);
+        w.send(&w2.public, Amount::from_coins(10), Amount::ZERO, &c, &mut p)
+            .unwrap();
 
-        c.add(json!(p.transactions).to_string().as_str());
+        c.add(
+            &Wallet::new().public,
+            p.select(c.utxos(), MAX_BLOCK_SIZE),
+            None,
+        )
+        .unwrap();
         p.clear();
 
-        assert_eq!(w2.calculate_balance(&c), b + 10.);
-        assert_eq!(w.calculate_balance(&c), b - 10.);
+        assert_eq!(w2.calculate_balance(&c), Amount::from_coins(10));
+        assert_eq!(
+            Some(w.calculate_balance(&c)),
+            b.checked_sub(Amount::from_coins(10))
+        );
+    }
+
+    // ✅ Test: Fees leave the sender and go to the miner
+    #[test]
+    fn fees() {
+        let mut w = Wallet::new();
+        let mut w2 = Wallet::new();
+        let mut m = Wallet::new();
+        let mut c = Chain::new();
+        let mut p = Pool::new();
+        let fee = Amount::from_coins(1);
+
+        c.add(&w.public, Vec::new(), None).unwrap();
+
+        w.send(&w2.public, Amount::from_coins(10), fee, &c, &mut p)
+            .unwrap();
+        w.send(&w2.public, Amount::from_coins(10), fee, &c, &mut p)
+            .unwrap();
+        assert!(w
+            .send(&w2.public, Amount::from_coins(28), fee, &c, &mut p)
+            .is_err());
+
+        c.add(&m.public, p.select(c.utxos(), MAX_BLOCK_SIZE), None)
+            .unwrap();
+
+        assert_eq!(w.calculate_balance(&c), Amount::from_coins(28));
+        assert_eq!(w2.calculate_balance(&c), Amount::from_coins(20));
+        assert_eq!(m.calculate_balance(&c), Amount::from_coins(52));
+    }
+
+    // ✅ Test: Payments alongside a pending joint transaction use the coins it leaves alone
+    #[test]
+    fn joint() {
+        let mut w = Wallet::new();
+        let o = Wallet::new();
+        let mut r = Wallet::new();
+        let mut c = Chain::new();
+        let mut p = Pool::new();
+
+        for miner in [&w, &w, &o] {
+            c.add(&miner.public, Vec::new(), None).unwrap();
+        }
+
+        let coins = [
+            c.utxos().owned_by(&w.public)[0].clone(),
+            c.utxos().owned_by(&o.public)[0].clone(),
+        ];
+        let mut joint = Transaction::build(
+            &coins,
+            vec![Output::new(&r.public, Amount::from_coins(100))],
+        );
+        joint.sign(&w, DEFAULT_CHAIN_ID).unwrap();
+        joint.sign(&o, DEFAULT_CHAIN_ID).unwrap();
+        p.update(joint, c.utxos()).unwrap();
+
+        for _ in 0..2 {
+            w.send(&r.public, Amount::from_coins(10), Amount::ZERO, &c, &mut p)
+                .unwrap();
+        }
+        assert_eq!(p.transactions().len(), 2);
+
+        c.add(&o.public, p.select(c.utxos(), MAX_BLOCK_SIZE), None)
+            .unwrap();
+
+        assert_eq!(r.calculate_balance(&c), Amount::from_coins(120));
+        assert_eq!(w.calculate_balance(&c), Amount::from_coins(30));
+    }
+
+    // ✅ Test: Vesting coins count and can be spent only once unlocked
+    #[test]
+    fn timelock() {
+        let mut w = Wallet::new();
+        let mut w2 = Wallet::new();
+        let mut c = Chain::new();
+        let mut p = Pool::new();
+
+        c.add(&w.public, Vec::new(), None).unwrap();
+        let vesting =
+            Output::new(&w2.public, Amount::from_coins(10)).with_timelock(Timelock::Blocks(2));
+        w.send_output(vesting, Amount::ZERO, &c, &mut p).unwrap();
+        c.add(&w.public, p.select(c.utxos(), MAX_BLOCK_SIZE), None)
+            .unwrap();
+
+        assert_eq!(w2.calculate_balance(&c), Amount::ZERO);
+        assert!(w2
+            .send(&w.public, Amount::from_coins(1), Amount::ZERO, &c, &mut p)
+            .is_err());
+
+        c.add(&w.public, Vec::new(), None).unwrap();
+
+        assert_eq!(w2.calculate_balance(&c), Amount::from_coins(10));
+        w2.send(&w.public, Amount::from_coins(1), Amount::ZERO, &c, &mut p)
+            .unwrap();
+    }
+
+    // ✅ Test: Treasury coins under a 2-of-3 lock move once two holders sign
+    #[test]
+    fn multisig() {
+        let mut w = Wallet::new();
+        let holders: Vec<Wallet> = (0..3).map(|_| Wallet::new()).collect();
+        let mut r = Wallet::new();
+        let mut c = Chain::new();
+        let mut p = Pool::new();
+        let lock = Lock::Multisig {
+            threshold: 2,
+            keys: holders.iter().map(|h| h.public).collect(),
+        };
+
+        c.add(&w.public, Vec::new(), None).unwrap();
+        w.send(
+            lock.clone(),
+            Amount::from_coins(30),
+            Amount::ZERO,
+            &c,
+            &mut p,
+        )
+        .unwrap();
+        c.add(&w.public, p.select(c.utxos(), MAX_BLOCK_SIZE), None)
+            .unwrap();
+        assert!(holders[0]
+            .propose(&lock, &r.public, Amount::from_coins(40), Amount::ZERO, &c)
+            .is_err());
+
+        let proposal = holders[0]
+            .propose(&lock, &r.public, Amount::from_coins(10), Amount::ZERO, &c)
+            .unwrap();
+        assert!(p.update(proposal.clone(), c.utxos()).is_err());
+
+        let mut cosigned = proposal.clone();
+        cosigned.sign(&holders[2], DEFAULT_CHAIN_ID).unwrap();
+        let mut late = proposal.clone();
+        late.sign(&holders[1], DEFAULT_CHAIN_ID).unwrap();
+        let mut t = proposal;
+        let mut forged = t.clone();
+        forged.inputs[0].signatures[1] = forged.inputs[0].signatures[0];
+        // ❌ A signature in the slot of a key that did not make it
+        assert!(t.combine(&forged, DEFAULT_CHAIN_ID).is_err());
+        t.combine(&cosigned, DEFAULT_CHAIN_ID).unwrap();
+
+        // Signatures beyond the threshold are left out, by both routes.
+        t.combine(&late, DEFAULT_CHAIN_ID).unwrap();
+        t.sign(&holders[1], DEFAULT_CHAIN_ID).unwrap();
+        assert_eq!(t.inputs[0].signatures.iter().flatten().count(), 2);
+
+        p.update(t, c.utxos()).unwrap();
+        c.add(&w.public, p.select(c.utxos(), MAX_BLOCK_SIZE), None)
+            .unwrap();
+
+        assert_eq!(r.calculate_balance(&c), Amount::from_coins(10));
+        assert_eq!(c.utxos().owned_by(lock)[0].1.amount, Amount::from_coins(20));
+    }
+
+    // ✅ Test: An HTLC is claimed with its secret, or refunded after its deadline
+    #[test]
+    fn htlc() {
+        let mut w = Wallet::new();
+        let mut r = Wallet::new();
+        let miner = Wallet::new().public;
+        let mut c = Chain::new();
+        let mut p = Pool::new();
+        let contract = |secret: &str| Htlc {
+            hash: Hash::new(secret).unwrap(),
+            receiver: r.public,
+            sender: w.public,
+            deadline: 4,
+        };
+        let (swap, expired) = (contract("secret"), contract("other"));
+
+        c.add(&w.public, Vec::new(), None).unwrap();
+        for htlc in [&swap, &expired] {
+            w.create_htlc(htlc, Amount::from_coins(10), Amount::ZERO, &c, &mut p)
+                .unwrap();
+        }
+        c.add(&miner, p.select(c.utxos(), MAX_BLOCK_SIZE), None)
+            .unwrap();
+
+        // ❌ Refunding before the deadline, or claiming with the wrong secret or wallet
+        assert!(w.refund_htlc(&expired, Amount::ZERO, &c, &mut p).is_err());
+        assert!(r
+            .claim_htlc(&swap, b"guess", Amount::ZERO, &c, &mut p)
+            .is_err());
+        assert!(w
+            .claim_htlc(&swap, b"secret", Amount::ZERO, &c, &mut p)
+            .is_err());
+
+        r.claim_htlc(&swap, b"secret", Amount::from_coins(1), &c, &mut p)
+            .unwrap();
+        c.add(&miner, p.select(c.utxos(), MAX_BLOCK_SIZE), None)
+            .unwrap();
+        assert_eq!(r.calculate_balance(&c), Amount::from_coins(9));
+
+        w.refund_htlc(&expired, Amount::ZERO, &c, &mut p).unwrap();
+        c.add(&miner, p.select(c.utxos(), MAX_BLOCK_SIZE), None)
+            .unwrap();
+        assert_eq!(w.calculate_balance(&c), Amount::from_coins(40));
+        assert!(r
+            .claim_htlc(&expired, b"other", Amount::ZERO, &c, &mut p)
+            .is_err());
     }
 }
@@ -1,18 +1,14 @@
 use hex;
 use sha2::{Digest, Sha256};
 
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone)]
 pub struct Hash(pub String);
 
 impl Hash {
     pub fn new(input: impl AsRef<[u8]>) -> Result<Hash, String> {
-        let mut h = Sha256::new();
-
-        h.update(input);
-
-        let out = hex::encode(h.finalize());
+        let out = hex::encode(digest(input));
 
         if out.len() != 64 {
             return Err("Hash must be 64 characters long.".to_owned());
@@ -27,4 +23,159 @@ impl Hash {
     pub fn blank() -> Self {
         Hash("0".repeat(64))
     }
+
+    pub fn from_bytes(bytes: &[u8; 32]) -> Self {
+        Hash(hex::encode(bytes))
+    }
+
+    /// Raw 32-byte digest, or `None` if this is not a valid hex digest.
+    pub fn to_bytes(&self) -> Option<[u8; 32]> {
+        hex::decode(&self.0).ok()?.try_into().ok()
+    }
+}
+
+/// Deserializes a `Hash`, rejecting anything but the hex of a 32-byte
+/// digest. For fields that are encoded as raw bytes, see `Header::encode`.
+pub fn deserialize_digest<'de, D: Deserializer<'de>>(d: D) -> Result<Hash, D::Error> {
+    let hash = Hash::deserialize(d)?;
+    match hash.to_bytes() {
+        Some(_) => Ok(hash),
+        None => Err(de::Error::custom("Hash must be a 32-byte hex digest.")),
+    }
+}
+
+/// Raw SHA-256 digest of `input`, for hot paths that need no hex.
+pub fn digest(input: impl AsRef<[u8]>) -> [u8; 32] {
+    Sha256::digest(input).into()
+}
+
+/// Position of a sibling node relative to the path being proven.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum Sibling {
+    Left(Hash),
+    Right(Hash),
+}
+
+/// Sibling hashes leading from a leaf up to the Merkle root.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct MerkleProof(pub Vec<Sibling>);
+
+impl MerkleProof {
+    /// Root obtained by hashing `leaf` up the tree along this path, or
+    /// `None` if the leaf or a sibling is not a valid digest.
+    pub fn root(&self, leaf: &Hash) -> Option<Hash> {
+        leaf.to_bytes()?;
+
+        self.0
+            .iter()
+            .try_fold(leaf.clone(), |node, sibling| match sibling {
+                Sibling::Left(left) => node_hash(left, &node),
+                Sibling::Right(right) => node_hash(&node, right),
+            })
+    }
+}
+
+/// Root of a Merkle tree over `leaves`, which must be valid digests. A node
+/// without a sibling is carried up to the next level unchanged, and an empty
+/// tree has a blank root.
+pub fn merkle_root(leaves: &[Hash]) -> Hash {
+    if leaves.is_empty() {
+        return Hash::blank();
+    }
+
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+
+    level.remove(0)
+}
+
+/// Path proving that `leaves[index]` is part of the tree with `merkle_root(leaves)`.
+pub fn merkle_proof(leaves: &[Hash], mut index: usize) -> Option<MerkleProof> {
+    if index >= leaves.len() {
+        return None;
+    }
+
+    let mut path = Vec::new();
+    let mut level = leaves.to_vec();
+
+    while level.len() > 1 {
+        if index % 2 == 1 {
+            path.push(Sibling::Left(level[index - 1].clone()));
+        } else if index + 1 < level.len() {
+            path.push(Sibling::Right(level[index + 1].clone()));
+        }
+
+        level = next_level(&level);
+        index /= 2;
+    }
+
+    Some(MerkleProof(path))
+}
+
+fn next_level(level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right).expect("leaves are valid digests"),
+            [single] => single.clone(),
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+/// Interior nodes are tagged so they can never be mistaken for a leaf.
+fn node_hash(left: &Hash, right: &Hash) -> Option<Hash> {
+    let mut input = vec![1u8];
+    input.extend_from_slice(&left.to_bytes()?);
+    input.extend_from_slice(&right.to_bytes()?);
+    Hash::new(input).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(n: usize) -> Vec<Hash> {
+        (0..n).map(|i| Hash::new(i.to_string()).unwrap()).collect()
+    }
+
+    #[test]
+    fn root() {
+        let l = leaves(3);
+
+        assert_eq!(merkle_root(&[]), Hash::blank());
+        assert_eq!(merkle_root(&l[..1]), l[0]);
+        let left = node_hash(&l[0], &l[1]).unwrap();
+        assert_eq!(merkle_root(&l), node_hash(&left, &l[2]).unwrap());
+    }
+
+    #[test]
+    fn proofs() {
+        for n in 1..=9 {
+            let l = leaves(n);
+            let root = merkle_root(&l);
+
+            for (i, leaf) in l.iter().enumerate() {
+                let proof = merkle_proof(&l, i).unwrap();
+                assert_eq!(proof.root(leaf), Some(root.clone()));
+                assert_ne!(proof.root(&Hash::blank()), Some(root.clone()));
+            }
+
+            assert!(merkle_proof(&l, n).is_none());
+        }
+    }
+
+    // ✅ Test: Hashes that are not digests give no root rather than a panic
+    #[test]
+    fn malformed() {
+        let l = leaves(2);
+        let proof = merkle_proof(&l, 0).unwrap();
+        let bad = Hash("not hex".to_string());
+
+        assert_eq!(proof.root(&bad), None);
+        assert_eq!(MerkleProof(vec![]).root(&bad), None);
+        assert_eq!(MerkleProof(vec![Sibling::Left(bad)]).root(&l[0]), None);
+    }
 }
@@ -1,6 +1,7 @@
 use std::time::{SystemTime, UNIX_EPOCH};
 
 pub mod hash;
+pub mod uint;
 //pub mod serialize;
 
 pub fn time() -> u64 {
@@ -0,0 +1,245 @@
+use std::{
+    cmp::Ordering,
+    ops::{Add, Div, Not, Shl, Shr, Sub},
+};
+
+/// Unsigned 256-bit integer, stored as four 64-bit limbs with the least
+/// significant first. Arithmetic saturates instead of overflowing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct U256(pub [u64; 4]);
+
+impl U256 {
+    pub const ZERO: U256 = U256([0; 4]);
+    pub const ONE: U256 = U256([1, 0, 0, 0]);
+    pub const MAX: U256 = U256([u64::MAX; 4]);
+
+    pub fn from_u64(n: u64) -> Self {
+        U256([n, 0, 0, 0])
+    }
+
+    /// Reads a big-endian number, e.g. a hash digest.
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        let mut limbs = [0; 4];
+        for (i, chunk) in bytes.chunks(8).enumerate() {
+            limbs[3 - i] = u64::from_be_bytes(chunk.try_into().unwrap());
+        }
+        U256(limbs)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == U256::ZERO
+    }
+
+    /// Number of significant bits.
+    pub fn bits(&self) -> u32 {
+        for i in (0..4).rev() {
+            if self.0[i] != 0 {
+                return 64 * i as u32 + 64 - self.0[i].leading_zeros();
+            }
+        }
+        0
+    }
+
+    /// Lowest 64 bits, or `u64::MAX` if the number does not fit.
+    pub fn saturating_u64(&self) -> u64 {
+        if self.bits() > 64 {
+            u64::MAX
+        } else {
+            self.0[0]
+        }
+    }
+
+    /// `self * num / den`, computed without losing the low bits in between.
+    /// Saturates to `MAX` when `den` is zero.
+    pub fn mul_div(self, num: u64, den: u64) -> Self {
+        if den == 0 {
+            return U256::MAX;
+        }
+
+        // Multiply into five limbs...
+        let mut wide = [0u64; 5];
+        let mut carry = 0u128;
+        for (w, limb) in wide.iter_mut().zip(self.0) {
+            let v = limb as u128 * num as u128 + carry;
+            *w = v as u64;
+            carry = v >> 64;
+        }
+        wide[4] = carry as u64;
+
+        // ...then divide them from the top down.
+        let mut rem = 0u128;
+        for limb in wide.iter_mut().rev() {
+            let v = (rem << 64) | *limb as u128;
+            *limb = (v / den as u128) as u64;
+            rem = v % den as u128;
+        }
+
+        if wide[4] != 0 {
+            return U256::MAX;
+        }
+        U256([wide[0], wide[1], wide[2], wide[3]])
+    }
+
+    fn bit(&self, i: u32) -> bool {
+        self.0[(i / 64) as usize] >> (i % 64) & 1 == 1
+    }
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for U256 {
+    type Output = U256;
+
+    fn add(self, rhs: U256) -> U256 {
+        let mut out = [0; 4];
+        let mut carry = false;
+        for (o, (a, b)) in out.iter_mut().zip(self.0.iter().zip(rhs.0)) {
+            let (v, c1) = a.overflowing_add(b);
+            let (v, c2) = v.overflowing_add(carry as u64);
+            *o = v;
+            carry = c1 || c2;
+        }
+
+        if carry {
+            U256::MAX
+        } else {
+            U256(out)
+        }
+    }
+}
+
+impl Sub for U256 {
+    type Output = U256;
+
+    fn sub(self, rhs: U256) -> U256 {
+        if rhs >= self {
+            return U256::ZERO;
+        }
+
+        let mut out = [0; 4];
+        let mut borrow = false;
+        for (o, (a, b)) in out.iter_mut().zip(self.0.iter().zip(rhs.0)) {
+            let (v, b1) = a.overflowing_sub(b);
+            let (v, b2) = v.overflowing_sub(borrow as u64);
+            *o = v;
+            borrow = b1 || b2;
+        }
+        U256(out)
+    }
+}
+
+impl Not for U256 {
+    type Output = U256;
+
+    fn not(self) -> U256 {
+        U256(self.0.map(|l| !l))
+    }
+}
+
+impl Shl<u32> for U256 {
+    type Output = U256;
+
+    fn shl(self, n: u32) -> U256 {
+        if n >= 256 {
+            return U256::ZERO;
+        }
+
+        let (limbs, bits) = ((n / 64) as usize, n % 64);
+        let mut out = [0; 4];
+        for (i, o) in out.iter_mut().enumerate().skip(limbs) {
+            *o = self.0[i - limbs] << bits;
+            if bits > 0 && i > limbs {
+                *o |= self.0[i - limbs - 1] >> (64 - bits);
+            }
+        }
+        U256(out)
+    }
+}
+
+impl Shr<u32> for U256 {
+    type Output = U256;
+
+    fn shr(self, n: u32) -> U256 {
+        if n >= 256 {
+            return U256::ZERO;
+        }
+
+        let (limbs, bits) = ((n / 64) as usize, n % 64);
+        let mut out = [0; 4];
+        for (i, o) in out.iter_mut().enumerate().take(4 - limbs) {
+            *o = self.0[i + limbs] >> bits;
+            if bits > 0 && i + limbs + 1 < 4 {
+                *o |= self.0[i + limbs + 1] << (64 - bits);
+            }
+        }
+        U256(out)
+    }
+}
+
+impl Div for U256 {
+    type Output = U256;
+
+    /// Long division, bit by bit. Dividing by zero gives `U256::MAX`.
+    fn div(self, rhs: U256) -> U256 {
+        if rhs.is_zero() {
+            return U256::MAX;
+        }
+
+        let mut quotient = U256::ZERO;
+        let mut rem = U256::ZERO;
+        for i in (0..self.bits()).rev() {
+            rem = rem << 1;
+            if self.bit(i) {
+                rem.0[0] |= 1;
+            }
+            if rem >= rhs {
+                rem = rem - rhs;
+                quotient.0[(i / 64) as usize] |= 1 << (i % 64);
+            }
+        }
+        quotient
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arithmetic() {
+        let a = U256::ONE << 200;
+        let b = U256::from_u64(3);
+
+        assert_eq!((a >> 200), U256::ONE);
+        assert_eq!((a << 100), U256::ZERO);
+        assert_eq!(a.bits(), 201);
+        assert_eq!(((a + b) - a), b);
+        assert_eq!((b - a), U256::ZERO);
+        assert_eq!((U256::MAX + b), U256::MAX);
+        assert_eq!((a / b).mul_div(3, 1) + U256::ONE, a);
+        assert_eq!(a.mul_div(6, 3), a << 1);
+        assert_eq!(U256::MAX.mul_div(2, 1), U256::MAX);
+        assert_eq!(U256::ONE.mul_div(1, 0), U256::MAX);
+        assert_eq!(!U256::ZERO, U256::MAX);
+        assert!(a > b && U256::MAX > a);
+    }
+
+    #[test]
+    fn bytes() {
+        let mut bytes = [0; 32];
+        bytes[0] = 0x80;
+        bytes[31] = 1;
+
+        assert_eq!(U256::from_be_bytes(bytes), (U256::ONE << 255) + U256::ONE);
+    }
+}
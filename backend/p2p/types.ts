@@ -5,32 +5,78 @@ interface WebSocketMessage<T> {
     data: T;
 }
 
+// 🔹 One step of a script
+type Op =
+    | { Push: string } // Hex bytes, such as a key, signature or hash
+    | { Num: number }
+    | "Dup"
+    | "Drop"
+    | "Swap"
+    | "Sha256"
+    | "Equal"
+    | "EqualVerify"
+    | "Verify"
+    | "CheckSig"
+    | "CheckSigVerify"
+    | "CheckMultisig"
+    | "CheckLockTimeVerify"
+    | "CheckSequenceVerify"
+    | "If"
+    | "NotIf"
+    | "Else"
+    | "EndIf";
+
+// 🔹 Who may spend an output: one key, M of N keys, or whoever satisfies a script
+type Lock =
+    | { Key: string } // Public key of recipient
+    | { Multisig: { threshold: number; keys: string[] } }
+    | { Script: Op[] };
+
+// 🔹 When an output becomes spendable: at a height or time, or a number of blocks or seconds after it was created
+type Timelock =
+    | "None"
+    | { Height: number }
+    | { Time: number }
+    | { Blocks: number }
+    | { Seconds: number };
+
 // 🔹 Defines an Output transaction
 interface TransactionOutput {
-    address: string; // Public key of recipient
-    amount: number; // Amount sent
+    lock: Lock; // Condition for spending the output
+    amount: string; // Amount sent, as a decimal string
+    timelock: Timelock; // Earliest block that may spend the output
+}
+
+// 🔹 Points at an output of an earlier transaction
+interface OutPoint {
+    txid: string; // Hash of the transaction holding the output
+    index: number; // Position of the output in that transaction
 }
 
 // 🔹 Defines the Input transaction
 interface TransactionInput {
-    address: string; // Public key of sender
-    amount: number; // Total input amount
-    signature: string; // Digital signature
-    timestamp: number; // Unix timestamp
+    outpoint: OutPoint; // Unspent output being spent
+    lock: Lock; // Lock of the output being spent
+    signatures: (string | null)[]; // One per key of the lock, null until signed
+    script: Op[]; // Unlocking script, for a script lock
+    timelock: Timelock; // Extra wait on top of the coin's own, checked by CheckSequenceVerify
 }
 
 // 🔹 Defines the full Transaction structure
 interface Transaction {
-    id: string; // Unique transaction ID
-    input: TransactionInput | null; // Transaction input details
+    inputs: TransactionInput[]; // Coins spent, empty for a coinbase
     outputs: TransactionOutput[]; // List of transaction outputs
+    height: number | null; // Block height committed to by a coinbase
+    lock_time: number; // Earliest block height, or Unix time from 500000000 on, that may include it
 }
 
 // 🔹 Defines a single Block in the blockchain
 interface Block {
-    data: string; // Block data (transactions, messages, etc.)
-    difficulty: number; // Proof-of-work difficulty level
-    index: number; // Position in the blockchain
+    merkle_root: string; // Merkle root of the transaction hashes
+    transactions: Transaction[]; // Transactions included in the block
+    memo: string | null; // Free-form data attached by the miner
+    bits: number; // Compact proof-of-work target
+    height: number; // Position in the blockchain
     nonce: number; // Nonce used for mining
     prev: string; // Previous block hash
     timestamp: number; // Unix timestamp when block was mined